@@ -0,0 +1,325 @@
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    body::Body,
+    extract::State,
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Deserializer};
+use tracing::instrument;
+use validator::Validate;
+
+use crate::{
+    AppState,
+    api::{
+        errors::ApiErrorResponse,
+        requests::{ApiJsonRequest, ApiPath, ApiQuery, Honeypot, Localized},
+        responses::{ApiJsonResponse, ApiPagination},
+    },
+    services::{
+        dead_letters::DeadLetter,
+        export::{ExportFormat, export},
+        privacy::{Erasure, Rotation},
+        quarantine::Quarantined,
+        submissions::{Submission, SubmissionFilter},
+    },
+};
+
+const DEFAULT_PAGE_LIMIT: u32 = 50;
+const MAX_PAGE_LIMIT: u32 = 500;
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ErasureRequest {
+    #[serde(deserialize_with = "trimmed")]
+    #[validate(email(message = "must be a valid email address"))]
+    email: String,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+impl Localized for ErasureRequest {}
+
+impl Honeypot for ErasureRequest {}
+
+/// Takes the surrounding whitespace off before the value is validated
+fn trimmed<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    String::deserialize(deserializer).map(|value| value.trim().to_string())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportParams {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PageParams {
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
+
+impl PageParams {
+    fn limit(&self) -> u32 {
+        self.limit.unwrap_or(DEFAULT_PAGE_LIMIT).clamp(1, MAX_PAGE_LIMIT)
+    }
+
+    fn offset(&self) -> u32 {
+        self.offset.unwrap_or_default()
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn list_dead_letters_handler(
+    State(state): State<Arc<AppState>>,
+    ApiQuery(page): ApiQuery<PageParams>,
+) -> Result<Json<ApiJsonResponse<Vec<DeadLetter>>>, ApiErrorResponse> {
+    let dead_letters = state.dead_letters.list(page.limit(), page.offset()).await?;
+
+    Ok(Json(ApiJsonResponse::with_data(dead_letters)))
+}
+
+#[instrument(skip_all)]
+pub async fn get_dead_letter_handler(
+    State(state): State<Arc<AppState>>,
+    ApiPath(id): ApiPath<i64>,
+) -> Result<Json<ApiJsonResponse<DeadLetter>>, ApiErrorResponse> {
+    let dead_letter = state.dead_letters.get(id).await?.ok_or(ApiErrorResponse::NotFound)?;
+
+    Ok(Json(ApiJsonResponse::with_data(dead_letter)))
+}
+
+#[instrument(skip_all)]
+pub async fn replay_dead_letter_handler(
+    State(state): State<Arc<AppState>>,
+    ApiPath(id): ApiPath<i64>,
+) -> Result<(StatusCode, Json<ApiJsonResponse>), ApiErrorResponse> {
+    let outbox_id = state.dead_letters.replay(id).await?.ok_or(ApiErrorResponse::NotFound)?;
+    state.outbox.wake();
+
+    tracing::info!("dead letter #{id} replayed as outbox job #{outbox_id}");
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(ApiJsonResponse::message(format!(
+            "The dead letter was queued for delivery as job #{outbox_id}"
+        ))),
+    ))
+}
+
+#[instrument(skip_all)]
+pub async fn list_quarantine_handler(
+    State(state): State<Arc<AppState>>,
+    ApiQuery(page): ApiQuery<PageParams>,
+) -> Result<Json<ApiJsonResponse<Vec<Quarantined>>>, ApiErrorResponse> {
+    let quarantined = state.quarantine.list(page.limit(), page.offset()).await?;
+
+    Ok(Json(ApiJsonResponse::with_data(quarantined)))
+}
+
+#[instrument(skip_all)]
+pub async fn get_quarantined_handler(
+    State(state): State<Arc<AppState>>,
+    ApiPath(id): ApiPath<i64>,
+) -> Result<Json<ApiJsonResponse<Quarantined>>, ApiErrorResponse> {
+    let quarantined = state.quarantine.get(id).await?.ok_or(ApiErrorResponse::NotFound)?;
+
+    Ok(Json(ApiJsonResponse::with_data(quarantined)))
+}
+
+#[instrument(skip_all)]
+pub async fn release_quarantined_handler(
+    State(state): State<Arc<AppState>>,
+    ApiPath(id): ApiPath<i64>,
+) -> Result<(StatusCode, Json<ApiJsonResponse>), ApiErrorResponse> {
+    let ticket = state.quarantine.release(id).await?.ok_or(ApiErrorResponse::NotFound)?;
+    state.outbox.wake();
+
+    tracing::info!("quarantined message #{id} released as ticket #{ticket}");
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(ApiJsonResponse::message(format!(
+            "The message was queued for delivery as ticket #{ticket}"
+        ))),
+    ))
+}
+
+#[instrument(skip_all)]
+pub async fn list_submissions_handler(
+    State(state): State<Arc<AppState>>,
+    ApiQuery(page): ApiQuery<PageParams>,
+    ApiQuery(filter): ApiQuery<SubmissionFilter>,
+) -> Result<Json<ApiJsonResponse<Vec<Submission>>>, ApiErrorResponse> {
+    let (limit, offset) = (page.limit(), page.offset());
+    let (submissions, total) = state.submissions.list(filter, limit, offset).await?;
+
+    Ok(Json(ApiJsonResponse::paginated(
+        submissions,
+        ApiPagination { limit, offset, total },
+    )))
+}
+
+#[instrument(skip_all)]
+pub async fn get_submission_handler(
+    State(state): State<Arc<AppState>>,
+    ApiPath(id): ApiPath<i64>,
+) -> Result<Json<ApiJsonResponse<Submission>>, ApiErrorResponse> {
+    let submission = state.submissions.get(id).await?.ok_or(ApiErrorResponse::NotFound)?;
+
+    Ok(Json(ApiJsonResponse::with_data(submission)))
+}
+
+/// Streams the matching submissions as a file download
+#[instrument(skip_all)]
+pub async fn export_submissions_handler(
+    State(state): State<Arc<AppState>>,
+    ApiQuery(params): ApiQuery<ExportParams>,
+    ApiQuery(filter): ApiQuery<SubmissionFilter>,
+) -> Response {
+    let format = params.format;
+    let stream = export(state.submissions.clone(), filter, format);
+    let disposition = format!(
+        "attachment; filename=\"submissions.{}\"",
+        format.extension()
+    );
+
+    tracing::info!("submissions exported as {:?}", format);
+
+    (
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        Body::from_stream(stream),
+    )
+        .into_response()
+}
+
+/// Erases everything stored for an email address, leaving only an audit record behind
+#[instrument(skip_all)]
+pub async fn erase_handler(
+    State(state): State<Arc<AppState>>,
+    ApiJsonRequest(request): ApiJsonRequest<ErasureRequest>,
+) -> Result<Json<ApiJsonResponse<Erasure>>, ApiErrorResponse> {
+    let erasure = state.privacy.erase(&request.email, request.reason).await?;
+    tracing::info!("erasure #{} done", erasure.id);
+
+    Ok(Json(ApiJsonResponse::with_data(erasure)))
+}
+
+#[instrument(skip_all)]
+pub async fn list_erasures_handler(
+    State(state): State<Arc<AppState>>,
+    ApiQuery(page): ApiQuery<PageParams>,
+) -> Result<Json<ApiJsonResponse<Vec<Erasure>>>, ApiErrorResponse> {
+    let erasures = state.privacy.list(page.limit(), page.offset()).await?;
+
+    Ok(Json(ApiJsonResponse::with_data(erasures)))
+}
+
+/// Re-encrypts every stored message with the active key, e.g. before an old key is dropped
+#[instrument(skip_all)]
+pub async fn rotate_keys_handler(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ApiJsonResponse<Rotation>>, ApiErrorResponse> {
+    let rotation = state.privacy.rotate_keys().await?;
+    tracing::info!(
+        "{} rows re-encrypted, {} skipped",
+        rotation.rotated,
+        rotation.skipped.len()
+    );
+
+    Ok(Json(ApiJsonResponse::with_data(rotation)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use reqwest::{StatusCode, header};
+    use serde_json::Value;
+
+    use crate::{api::handlers::tests::serve_app, configs::AppConfigs, shutdown::Shutdown};
+
+    const TOKEN: &str = "an admin token of at least 32 chars";
+
+    async fn spawn_admin() -> (String, Shutdown) {
+        let mut configs = AppConfigs::for_tests(&[]);
+        configs.admin_tokens = vec![TOKEN.to_string()];
+        let (base_url, _, shutdown) = serve_app(configs).await;
+
+        (format!("{base_url}/api/v1/admin"), shutdown)
+    }
+
+    async fn get(url: &str) -> (StatusCode, Value) {
+        let response = reqwest::Client::new().get(url).bearer_auth(TOKEN).send().await.unwrap();
+        (response.status(), response.json().await.unwrap())
+    }
+
+    async fn post(url: &str, body: &'static str) -> (StatusCode, Value) {
+        let response = reqwest::Client::new()
+            .post(url)
+            .bearer_auth(TOKEN)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await
+            .unwrap();
+        (response.status(), response.json().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_parameters_with_the_api_error_shape() {
+        let (admin_url, shutdown) = spawn_admin().await;
+
+        let (status, body) = get(&format!("{admin_url}/submissions?limit=abc")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["meta"]["message"], "Invalid query parameters");
+        assert_eq!(body["errors"][0]["source"], "$query");
+        assert!(
+            body["errors"][0]["description"][0].as_str().unwrap().contains("limit"),
+            "{body}"
+        );
+
+        let (status, body) = get(&format!("{admin_url}/dead-letters/abc")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["meta"]["message"], "Invalid path parameters");
+        assert_eq!(body["errors"][0]["source"], "$path");
+
+        let (status, body) = get(&format!("{admin_url}/submissions?limit=10")).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["meta"]["pagination"]["limit"], 10);
+
+        shutdown.drain(Duration::from_secs(1)).await;
+    }
+
+    #[tokio::test]
+    async fn validates_the_erasure_request() {
+        let (admin_url, shutdown) = spawn_admin().await;
+        let url = format!("{admin_url}/erasures");
+
+        let (status, body) = post(&url, r#"{"email": "jane@example.com""#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["errors"][0]["source"], "$body");
+
+        let (status, body) = post(&url, r#"{"email": "not an address"}"#).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["errors"][0]["source"], "email");
+
+        let (status, body) = post(
+            &url,
+            r#"{"email": "  Jane@Example.com ", "reason": "ticket 42"}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["data"]["kind"], "request");
+        assert_eq!(body["data"]["reason"], "ticket 42");
+
+        shutdown.drain(Duration::from_secs(1)).await;
+    }
+}
@@ -0,0 +1,44 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderName, header},
+    middleware::Next,
+    response::Response,
+};
+
+use super::errors::ApiErrorResponse;
+use crate::AppState;
+
+pub const X_API_KEY: HeaderName = HeaderName::from_static("x-api-key");
+
+/// Lets the request through only when it carries one of the configured admin tokens,
+/// either as a bearer token or as an API key
+pub async fn admin_auth(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiErrorResponse> {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| request.headers().get(X_API_KEY).and_then(|value| value.to_str().ok()))
+        .ok_or(ApiErrorResponse::Unauthorized)?;
+
+    let is_known = state
+        .configs
+        .admin_tokens
+        .iter()
+        .any(|known| constant_time_eq(known.as_bytes(), token.trim().as_bytes()));
+    if !is_known {
+        return Err(ApiErrorResponse::Unauthorized);
+    }
+
+    Ok(next.run(request).await)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
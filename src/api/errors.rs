@@ -1,17 +1,26 @@
-use askama::Error as TemplateError;
+use std::time::Duration;
+
 use axum::{
     Json,
-    extract::rejection::JsonRejection as JsonErrors,
-    http::StatusCode,
+    extract::rejection::{
+        JsonRejection as JsonErrors, PathRejection as PathErrors, QueryRejection as QueryErrors,
+    },
+    http::{StatusCode, header},
     response::{IntoResponse, Response},
 };
 use convert_case::{Case, Casing};
-use lettre::{error::Error as CommonError, transport::smtp::Error as SmtpError};
+use lettre::{
+    address::AddressError,
+    error::Error as CommonError,
+    transport::{file::Error as FileError, smtp::Error as SmtpError},
+};
+use minijinja::Error as TemplateError;
 use serde::{Serialize, ser::SerializeStruct};
 use thiserror::Error;
 use validator::{ValidationErrors, ValidationErrorsKind};
 
 use super::responses::ApiJsonResponse;
+use crate::services::i18n::I18n;
 
 const NUMBERS_OF_FIELDS_TO_SERIALISE: usize = 2;
 
@@ -21,25 +30,155 @@ pub enum ApiErrorResponse {
     #[error(transparent)]
     JsonErrors(#[from] JsonErrors),
 
+    /// The JSON parsed, but didn't fit the payload, the error keeps the path of the field
+    #[error("Failed to deserialize the JSON body into the target type: {0}")]
+    JsonDataErrors(#[from] serde_path_to_error::Error<serde_json::Error>),
+
+    #[error(transparent)]
+    QueryErrors(#[from] QueryErrors),
+
+    #[error(transparent)]
+    PathErrors(#[from] PathErrors),
+
     #[error(transparent)]
     ValidationErrors(#[from] ValidationErrors),
 
     #[error(transparent)]
     EmailErrors(#[from] EmailErrors),
+
+    #[error(transparent)]
+    StorageErrors(#[from] StorageErrors),
+
+    #[error(transparent)]
+    CaptchaErrors(#[from] CaptchaErrors),
+
+    #[error("missing or invalid admin credentials")]
+    Unauthorized,
+
+    #[error("resource not found")]
+    NotFound,
+
+    #[error("too many requests, retry after {0:?}")]
+    TooManyRequests(Duration),
+
+    #[error("invalid idempotency key")]
+    InvalidIdempotencyKey,
+
+    #[error("idempotency key is in use by another request")]
+    IdempotencyKeyInFlight,
+
+    #[error("idempotency key was used with a different payload")]
+    IdempotencyKeyMismatch,
+
+    #[error("payload too large")]
+    PayloadTooLarge,
+
+    #[error("the same message was already sent")]
+    DuplicateSubmission,
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Error)]
 pub enum EmailErrors {
+    #[error(transparent)]
+    AddressError(#[from] AddressError),
+
     #[error(transparent)]
     CommonError(#[from] CommonError),
 
     #[error(transparent)]
     SmtpError(#[from] SmtpError),
 
+    #[error(transparent)]
+    FileError(#[from] FileError),
+
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+
     #[error(transparent)]
     TemplateError(#[from] TemplateError),
 }
 
+impl EmailErrors {
+    /// Tells whether another attempt may succeed, a rejected address, a broken message or an
+    /// SMTP 5xx answer fail the same way every time
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::AddressError(_) | Self::TemplateError(_) => false,
+            Self::CommonError(err) => matches!(err, CommonError::Io(_)),
+            Self::SmtpError(err) => !err.is_permanent(),
+            Self::FileError(_) | Self::IoError(_) => true,
+        }
+    }
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Error)]
+pub enum StorageErrors {
+    #[error(transparent)]
+    SqliteError(#[from] rusqlite::Error),
+
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    JoinError(#[from] tokio::task::JoinError),
+
+    #[error(transparent)]
+    CsvError(#[from] csv::Error),
+
+    #[error(transparent)]
+    CryptoErrors(#[from] CryptoErrors),
+
+    #[error("the storage connection is poisoned")]
+    PoisonError,
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Error)]
+pub enum CaptchaErrors {
+    #[error(transparent)]
+    HttpError(#[from] reqwest::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum NotifyErrors {
+    #[error(transparent)]
+    HttpError(#[from] reqwest::Error),
+
+    #[error("unexpected response status {0}")]
+    UnexpectedStatus(reqwest::StatusCode),
+}
+
+impl NotifyErrors {
+    /// Tells whether another attempt may succeed, the rejections of the receiver are final
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::HttpError(err) => !err.is_builder(),
+            Self::UnexpectedStatus(status) => {
+                status.is_server_error()
+                    || *status == reqwest::StatusCode::REQUEST_TIMEOUT
+                    || *status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            }
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum CryptoErrors {
+    #[error("unknown encryption key <{0}>")]
+    UnknownKey(String),
+
+    #[error("malformed encrypted value")]
+    Malformed,
+
+    #[error("couldn't encrypt the value")]
+    Encryption,
+
+    #[error("couldn't decrypt the value")]
+    Decryption,
+}
+
 #[derive(Debug)]
 #[must_use]
 pub struct FieldError {
@@ -84,12 +223,28 @@ impl IntoResponse for ApiErrorResponse {
     fn into_response(self) -> Response {
         // Constants for error messages
         const JSON_ERROR_MSG: &str = "Invalid JSON format";
+        const QUERY_ERROR_MSG: &str = "Invalid query parameters";
+        const PATH_ERROR_MSG: &str = "Invalid path parameters";
         const VALIDATION_ERROR_MSG: &str = "Invalid JSON validation";
         const EMAIL_ERROR_MSG: &str = "Unable to send email";
+        const STORAGE_ERROR_MSG: &str = "Unable to store the message";
+        const CAPTCHA_ERROR_MSG: &str = "Unable to verify the captcha";
+        const UNAUTHORIZED_ERROR_MSG: &str = "Missing or invalid admin credentials";
+        const NOT_FOUND_ERROR_MSG: &str = "The requested resource was not found";
+        const TOO_MANY_REQUESTS_ERROR_MSG: &str = "Too many requests, please try again later";
+        const INVALID_IDEMPOTENCY_KEY_ERROR_MSG: &str =
+            "The Idempotency-Key header must be between 1 and 255 visible chars";
+        const IDEMPOTENCY_KEY_IN_FLIGHT_ERROR_MSG: &str =
+            "A request with this Idempotency-Key is still in progress";
+        const IDEMPOTENCY_KEY_MISMATCH_ERROR_MSG: &str =
+            "The Idempotency-Key was already used with a different payload";
+        const PAYLOAD_TOO_LARGE_ERROR_MSG: &str = "The request payload is too large";
+        const DUPLICATE_SUBMISSION_ERROR_MSG: &str =
+            "The same message was already sent, no need to send it again";
 
         let (status_code, response) = match self {
             /* Json handling */
-            ApiErrorResponse::JsonErrors(err) => {
+            err @ (ApiErrorResponse::JsonErrors(_) | ApiErrorResponse::JsonDataErrors(_)) => {
                 let errors = vec![FieldError::new(
                     "$body",
                     vec![capitalize(err.to_string().split(" at line").next().unwrap_or_default())],
@@ -101,6 +256,24 @@ impl IntoResponse for ApiErrorResponse {
                 )
             }
 
+            /* Query string and path handling */
+            ApiErrorResponse::QueryErrors(err) => {
+                let errors = vec![FieldError::new("$query", vec![err.body_text()])];
+
+                (
+                    err.status(),
+                    ApiJsonResponse::error(QUERY_ERROR_MSG, Some(errors)),
+                )
+            }
+            ApiErrorResponse::PathErrors(err) => {
+                let errors = vec![FieldError::new("$path", vec![err.body_text()])];
+
+                (
+                    err.status(),
+                    ApiJsonResponse::error(PATH_ERROR_MSG, Some(errors)),
+                )
+            }
+
             /* Validator handling */
             ApiErrorResponse::ValidationErrors(err) => {
                 let mut errors = collect_field_errors(&err);
@@ -124,6 +297,75 @@ impl IntoResponse for ApiErrorResponse {
                     ApiJsonResponse::error(EMAIL_ERROR_MSG, None),
                 )
             }
+
+            /* Storage handling [outbox and friends] */
+            ApiErrorResponse::StorageErrors(err) => {
+                // Send the error to sentry
+                sentry::capture_error(&err);
+
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    ApiJsonResponse::error(STORAGE_ERROR_MSG, None),
+                )
+            }
+
+            /* Captcha handling [provider unreachable or broken] */
+            ApiErrorResponse::CaptchaErrors(err) => {
+                // Send the error to sentry
+                sentry::capture_error(&err);
+
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    ApiJsonResponse::error(CAPTCHA_ERROR_MSG, None),
+                )
+            }
+
+            /* Admin handling */
+            ApiErrorResponse::Unauthorized => (
+                StatusCode::UNAUTHORIZED,
+                ApiJsonResponse::error(UNAUTHORIZED_ERROR_MSG, None),
+            ),
+            ApiErrorResponse::NotFound => (
+                StatusCode::NOT_FOUND,
+                ApiJsonResponse::error(NOT_FOUND_ERROR_MSG, None),
+            ),
+
+            /* Idempotency handling */
+            ApiErrorResponse::InvalidIdempotencyKey => (
+                StatusCode::BAD_REQUEST,
+                ApiJsonResponse::error(INVALID_IDEMPOTENCY_KEY_ERROR_MSG, None),
+            ),
+            ApiErrorResponse::IdempotencyKeyInFlight => (
+                StatusCode::CONFLICT,
+                ApiJsonResponse::error(IDEMPOTENCY_KEY_IN_FLIGHT_ERROR_MSG, None),
+            ),
+            ApiErrorResponse::IdempotencyKeyMismatch => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                ApiJsonResponse::error(IDEMPOTENCY_KEY_MISMATCH_ERROR_MSG, None),
+            ),
+            ApiErrorResponse::PayloadTooLarge => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                ApiJsonResponse::error(PAYLOAD_TOO_LARGE_ERROR_MSG, None),
+            ),
+
+            /* Duplicate handling */
+            ApiErrorResponse::DuplicateSubmission => (
+                StatusCode::CONFLICT,
+                ApiJsonResponse::error(DUPLICATE_SUBMISSION_ERROR_MSG, None),
+            ),
+
+            /* Rate limiting */
+            ApiErrorResponse::TooManyRequests(retry_after) => {
+                // Rounded up, so the client never comes back a moment too early
+                let retry_after = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(header::RETRY_AFTER, retry_after.to_string())],
+                    Json(ApiJsonResponse::error(TOO_MANY_REQUESTS_ERROR_MSG, None)),
+                )
+                    .into_response();
+            }
         };
 
         (status_code, Json(response)).into_response()
@@ -187,6 +429,31 @@ fn collect_field_errors(errors: &ValidationErrors) -> Vec<FieldError> {
     collected
 }
 
+/// Replaces the messages with their translations, looked up by the error codes
+pub(super) fn localize_validation_errors(errors: &mut ValidationErrors, i18n: &I18n, locale: &str) {
+    for kind in errors.errors_mut().values_mut() {
+        match kind {
+            ValidationErrorsKind::Field(field_errs) => {
+                for err in field_errs {
+                    if let Some(message) =
+                        i18n.translate(locale, &format!("validation.{}", err.code))
+                    {
+                        err.message = Some(message.to_string().into());
+                    }
+                }
+            }
+            ValidationErrorsKind::Struct(struct_errs) => {
+                localize_validation_errors(struct_errs, i18n, locale);
+            }
+            ValidationErrorsKind::List(list_errs) => {
+                for item_errs in list_errs.values_mut() {
+                    localize_validation_errors(item_errs, i18n, locale);
+                }
+            }
+        }
+    }
+}
+
 fn normalize_source(source: &str) -> String {
     if source.is_empty() { "$schema".to_string() } else { source.to_string() }
 }
@@ -1,13 +1,26 @@
-use std::sync::Arc;
+use std::{net::IpAddr, sync::Arc};
 
-use axum::{Json, extract::State};
+use axum::{
+    Extension, Json,
+    extract::State,
+    http::{HeaderMap, StatusCode, header},
+};
+use tower_http::request_id::RequestId;
 use tracing::instrument;
+use validator::{ValidationError, ValidationErrors};
 
 use crate::{
     AppState,
     api::{
-        errors::ApiErrorResponse, models::LetsStartForm, requests::ApiJsonRequest,
-        responses::ApiJsonResponse,
+        errors::{ApiErrorResponse, localize_validation_errors},
+        models::LetsStartForm,
+        rate_limit::ClientIp,
+        requests::{ApiJsonRequest, ApiLocale},
+        responses::{ApiFormToken, ApiJsonResponse},
+    },
+    services::{
+        captcha::CaptchaVerifier, duplicates::DuplicateAction, outbox::Queued, spam::SpamAction,
+        submissions::SubmissionOrigin,
     },
 };
 
@@ -16,14 +29,405 @@ pub async fn alive_handler() -> Json<ApiJsonResponse> {
     Json(ApiJsonResponse::message("The server is alive and well :)"))
 }
 
+/// Issues the token the form sends back, proving it was rendered a while ago
+#[instrument(skip_all)]
+pub async fn form_token_handler(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ApiJsonResponse<ApiFormToken>>, ApiErrorResponse> {
+    let form_tokens = state.form_tokens.as_ref().ok_or(ApiErrorResponse::NotFound)?;
+
+    Ok(Json(ApiJsonResponse::with_data(ApiFormToken {
+        token: form_tokens.issue(),
+    })))
+}
+
 #[instrument(skip_all)]
 pub async fn send_message_handler(
     State(state): State<Arc<AppState>>,
-    ApiJsonRequest(request): ApiJsonRequest<LetsStartForm>,
-) -> Result<Json<ApiJsonResponse>, ApiErrorResponse> {
-    state.mailer.send_message(request, &state.configs).await?;
+    ApiLocale(header_locale): ApiLocale,
+    Extension(ClientIp(client_ip)): Extension<ClientIp>,
+    request_id: Option<Extension<RequestId>>,
+    headers: HeaderMap,
+    ApiJsonRequest(mut request): ApiJsonRequest<LetsStartForm>,
+) -> Result<(StatusCode, Json<ApiJsonResponse>), ApiErrorResponse> {
+    let origin = SubmissionOrigin {
+        request_id: request_id
+            .and_then(|Extension(id)| id.header_value().to_str().ok().map(str::to_string)),
+        client_ip,
+        origin: headers
+            .get(header::ORIGIN)
+            .and_then(|origin| origin.to_str().ok())
+            .map(str::to_string),
+    };
+
+    // The locale is pinned here, so the emails speak the same language as the form did
+    let candidates = request.locale.as_deref().into_iter().chain([header_locale.as_str()]);
+    request.locale = Some(state.i18n.negotiate(candidates));
+
+    // Bots learn nothing from a rejection, so they get the very same answer as humans
+    if request.honeypot_filled {
+        tracing::info!("honeypot filled in, the message is dropped");
+        return Ok(accepted());
+    }
+
+    if let Some(code) = state.domain_policy.check(&request.email).code() {
+        return Err(field_error(&state, &request, "email", code));
+    }
+
+    if let Some(form_tokens) = &state.form_tokens {
+        form_tokens
+            .check(request.form_token.as_deref().unwrap_or_default())
+            .map_err(|err| field_error(&state, &request, "form_token", err.code()))?;
+    }
+
+    // Keyed by the address itself, so the display name or the case can't dodge the limit, and
+    // checked ahead of the captcha, so a flood never reaches the provider
+    let email = request.email.trim().to_lowercase();
+    state.email_rate_limiter.check(&email).map_err(|retry_after| {
+        tracing::warn!("rate limit exceeded by an email address");
+        ApiErrorResponse::TooManyRequests(retry_after)
+    })?;
+
+    if let Some(captcha) = &state.captcha {
+        verify_captcha(&state, captcha.as_ref(), &request, client_ip).await?;
+    }
+
+    let verdict = state.spam.verdict(&request);
+    match verdict.action {
+        SpamAction::Reject => {
+            tracing::info!("message rejected as spam, score {:.1}", verdict.score);
+            return Err(field_error(
+                &state,
+                &request,
+                "project_description",
+                "spam_rejected",
+            ));
+        }
+        SpamAction::Quarantine => {
+            let id = state.quarantine.put(&request, &verdict, origin).await?;
+            tracing::info!("message quarantined as #{id}, score {:.1}", verdict.score);
+            return Ok(accepted());
+        }
+        SpamAction::Tag | SpamAction::Accept => {}
+    }
+
+    let claim = state.duplicates.map(|duplicates| duplicates.claim(&request));
+    let Some(Queued { submission_id, ticket }) =
+        state.outbox.enqueue(&request, origin, claim).await?
+    else {
+        tracing::info!("duplicate message, it isn't sent again");
+        return match state.configs.duplicate_action {
+            DuplicateAction::Accept => Ok(accepted()),
+            DuplicateAction::Reject => Err(ApiErrorResponse::DuplicateSubmission),
+        };
+    };
+    tracing::info!("submission #{submission_id} queued as ticket #{ticket}");
+
+    Ok(accepted())
+}
+
+fn accepted() -> (StatusCode, Json<ApiJsonResponse>) {
+    (
+        StatusCode::ACCEPTED,
+        Json(ApiJsonResponse::message(
+            "The message was accepted for delivery",
+        )),
+    )
+}
+
+/// Reports a missing or rejected captcha token the same way as any other invalid field
+async fn verify_captcha(
+    state: &AppState,
+    captcha: &dyn CaptchaVerifier,
+    form: &LetsStartForm,
+    client_ip: IpAddr,
+) -> Result<(), ApiErrorResponse> {
+    let token = form.captcha_token.as_deref().map(str::trim).filter(|token| !token.is_empty());
+    let code = match token {
+        None => "captcha_required",
+        Some(token) if !captcha.verify(token, client_ip).await? => "captcha_invalid",
+        Some(_) => return Ok(()),
+    };
+
+    Err(field_error(state, form, "captcha_token", code))
+}
+
+/// Reports a check made outside of the validator as one more invalid field
+fn field_error(
+    state: &AppState,
+    form: &LetsStartForm,
+    field: &'static str,
+    code: &'static str,
+) -> ApiErrorResponse {
+    let mut errors = ValidationErrors::new();
+    errors.add(field, ValidationError::new(code));
+    let locale = form.locale.as_deref().unwrap_or(state.i18n.default_locale());
+    localize_validation_errors(&mut errors, &state.i18n, locale);
+
+    errors.into()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use lettre::Message;
+    use reqwest::StatusCode;
+    use serde_json::{Value, json};
+
+    use crate::{
+        build_app,
+        configs::AppConfigs,
+        services::{
+            captcha::tests::{Calls, spawn_siteverify},
+            transport::MemoryMailTransport,
+        },
+        shutdown::Shutdown,
+    };
+
+    /// Serves the whole app on a random port, delivering into the returned transport
+    async fn spawn_app(overrides: &[(&str, &str)]) -> (String, MemoryMailTransport, Shutdown) {
+        let (base_url, transport, shutdown) = serve_app(AppConfigs::for_tests(overrides)).await;
+
+        (
+            format!("{base_url}/api/v1/send-message"),
+            transport,
+            shutdown,
+        )
+    }
+
+    /// Serves the app built from the configs on a random port, returns its base URL
+    pub(crate) async fn serve_app(configs: AppConfigs) -> (String, MemoryMailTransport, Shutdown) {
+        let transport = MemoryMailTransport::default();
+        let shutdown = Shutdown::new();
+        let app =
+            build_app(configs, std::sync::Arc::new(transport.clone()), &shutdown).await.unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let app = app.into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        (base_url, transport, shutdown)
+    }
+
+    fn form(email: &str) -> Value {
+        json!({
+            "email": email,
+            "minBudget": 1000,
+            "maxBudget": 5000,
+            "name": "Jane Doe",
+            "projectDescription": "We need a backend service for our new product, with an API and a database.",
+        })
+    }
+
+    async fn post(url: &str, form: &Value) -> (StatusCode, Value) {
+        let response = reqwest::Client::new().post(url).json(form).send().await.unwrap();
+        (response.status(), response.json().await.unwrap())
+    }
+
+    /// Waits for the outbox worker to hand the messages over to the transport
+    async fn sent(transport: &MemoryMailTransport, expected: usize) -> Vec<Message> {
+        for _ in 0..100 {
+            if transport.messages().len() >= expected {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        transport.messages()
+    }
+
+    #[tokio::test]
+    async fn delivers_an_accepted_message() {
+        let (url, transport, shutdown) = spawn_app(&[]).await;
+
+        let (status, body) = post(&url, &form("jane@example.com")).await;
+
+        assert_eq!(status, StatusCode::ACCEPTED, "{body}");
+        let messages = sent(&transport, 1).await;
+        let [message] = messages.as_slice() else {
+            panic!("expected one message, got {}", messages.len());
+        };
+        let formatted = String::from_utf8_lossy(&message.formatted()).into_owned();
+        assert!(
+            formatted.contains("Subject: Let's start #1: Jane Doe, 1000-5000 USD"),
+            "{formatted}"
+        );
+        assert!(
+            formatted.contains(r#"Reply-To: "Jane Doe" <jane@example.com>"#),
+            "{formatted}"
+        );
+
+        shutdown.drain(Duration::from_secs(1)).await;
+    }
+
+    #[tokio::test]
+    async fn sends_nothing_for_an_invalid_form() {
+        let (url, transport, shutdown) = spawn_app(&[]).await;
+
+        let (status, body) = post(&url, &form("not an address")).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{body}");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(transport.messages().is_empty());
+
+        shutdown.drain(Duration::from_secs(1)).await;
+    }
+
+    /// The app with the captcha checked against a stub provider
+    async fn spawn_app_with_captcha(
+        status: StatusCode,
+        success: bool,
+        overrides: &[(&str, &str)],
+    ) -> (String, Calls, Shutdown) {
+        let (verify_url, calls) = spawn_siteverify(status, success).await;
+        let mut overrides = overrides.to_vec();
+        overrides.extend([
+            ("captcha_provider", "turnstile"),
+            ("captcha_secret", "captcha_secret"),
+            ("captcha_verify_url", verify_url.as_str()),
+        ]);
+        let (url, _, shutdown) = spawn_app(&overrides).await;
+
+        (url, calls, shutdown)
+    }
+
+    fn with_captcha(mut form: Value, token: &str) -> Value {
+        form["captchaToken"] = token.into();
+        form
+    }
+
+    #[tokio::test]
+    async fn accepts_a_vouched_captcha_token() {
+        let (url, calls, shutdown) = spawn_app_with_captcha(StatusCode::OK, true, &[]).await;
+
+        let (status, body) = post(&url, &with_captcha(form("jane@example.com"), "token")).await;
+
+        assert_eq!(status, StatusCode::ACCEPTED, "{body}");
+        assert_eq!(calls.lock().unwrap().len(), 1);
+
+        shutdown.drain(Duration::from_secs(1)).await;
+    }
+
+    #[tokio::test]
+    async fn rejects_a_refused_captcha_token() {
+        let (url, _, shutdown) = spawn_app_with_captcha(StatusCode::OK, false, &[]).await;
+
+        let (status, body) = post(&url, &with_captcha(form("jane@example.com"), "token")).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{body}");
+
+        shutdown.drain(Duration::from_secs(1)).await;
+    }
+
+    #[tokio::test]
+    async fn requires_the_captcha_token() {
+        let (url, calls, shutdown) = spawn_app_with_captcha(StatusCode::OK, true, &[]).await;
+
+        let (status, body) = post(&url, &form("jane@example.com")).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{body}");
+        assert!(
+            calls.lock().unwrap().is_empty(),
+            "the provider isn't asked without a token"
+        );
+
+        shutdown.drain(Duration::from_secs(1)).await;
+    }
+
+    #[tokio::test]
+    async fn answers_503_when_the_captcha_provider_fails() {
+        let (url, _, shutdown) =
+            spawn_app_with_captcha(StatusCode::INTERNAL_SERVER_ERROR, false, &[]).await;
+
+        let (status, body) = post(&url, &with_captcha(form("jane@example.com"), "token")).await;
+
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{body}");
+
+        shutdown.drain(Duration::from_secs(1)).await;
+    }
+
+    #[tokio::test]
+    async fn limits_the_address_ahead_of_the_captcha() {
+        let overrides = [("email_rate_limit_burst", "1")];
+        let (url, calls, shutdown) =
+            spawn_app_with_captcha(StatusCode::OK, false, &overrides).await;
+        let form = with_captcha(form("jane@example.com"), "token");
+
+        let (first, _) = post(&url, &form).await;
+        let (second, body) = post(&url, &form).await;
+
+        assert_eq!(first, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(second, StatusCode::TOO_MANY_REQUESTS, "{body}");
+        assert_eq!(
+            calls.lock().unwrap().len(),
+            1,
+            "the limited request never reaches the provider"
+        );
+
+        shutdown.drain(Duration::from_secs(1)).await;
+    }
+
+    #[tokio::test]
+    async fn limits_the_address_ahead_of_the_quarantine() {
+        let (url, transport, shutdown) = spawn_app(&[("email_rate_limit_burst", "1")]).await;
+        let mut form = form("jane@example.com");
+        form["projectDescription"] =
+            "We offer casino crypto backlinks for your site, and the best link building deals."
+                .into();
+
+        let (first, _) = post(&url, &form).await;
+        let (second, body) = post(&url, &form).await;
+
+        assert_eq!(first, StatusCode::ACCEPTED);
+        assert_eq!(second, StatusCode::TOO_MANY_REQUESTS, "{body}");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(
+            transport.messages().is_empty(),
+            "the quarantined message is held back"
+        );
+
+        shutdown.drain(Duration::from_secs(1)).await;
+    }
+
+    #[tokio::test]
+    async fn replays_an_idempotent_request_without_a_rate_limit_token() {
+        let (url, transport, shutdown) = spawn_app(&[("ip_rate_limit_burst", "1")]).await;
+        let client = reqwest::Client::new();
+        let send = |key: &'static str| {
+            client.post(&url).header("idempotency-key", key).json(&form("jane@example.com")).send()
+        };
+
+        let first = send("key-1").await.unwrap();
+        let replayed = send("key-1").await.unwrap();
+        let limited = send("key-2").await.unwrap();
+
+        assert_eq!(first.status(), StatusCode::ACCEPTED);
+        assert_eq!(replayed.status(), StatusCode::ACCEPTED);
+        assert_eq!(replayed.headers()["idempotent-replayed"], "true");
+        assert_eq!(
+            replayed.headers()["content-type"],
+            first.headers()["content-type"]
+        );
+        assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(sent(&transport, 1).await.len(), 1);
+
+        shutdown.drain(Duration::from_secs(1)).await;
+    }
+
+    #[tokio::test]
+    async fn points_at_the_field_that_doesnt_fit() {
+        let (url, _, shutdown) = spawn_app(&[]).await;
+        let mut form = form("jane@example.com");
+        form["minBudget"] = "a lot".into();
+
+        let (status, body) = post(&url, &form).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+        let error = body.to_string();
+        assert!(error.contains("minBudget: invalid type: string"), "{error}");
 
-    Ok(Json(ApiJsonResponse::message(
-        "The message was successfully sent",
-    )))
+        shutdown.drain(Duration::from_secs(1)).await;
+    }
 }
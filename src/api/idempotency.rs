@@ -0,0 +1,106 @@
+use std::sync::Arc;
+
+use axum::{
+    body::{Body, to_bytes},
+    extract::{MatchedPath, Request, State},
+    http::{HeaderName, HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+
+use super::errors::ApiErrorResponse;
+use crate::{
+    AppState,
+    services::idempotency::{Reservation, StoredResponse},
+};
+
+pub const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+pub const IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+const MAX_KEY_LEN: usize = 255;
+/// Same as the default limit of the JSON extractor
+const MAX_BODY_SIZE: usize = 2 * 1024 * 1024;
+
+/// Answers the repeats of a request carrying an `Idempotency-Key` with the first response
+pub async fn idempotency(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiErrorResponse> {
+    let Some(key) = request.headers().get(IDEMPOTENCY_KEY) else {
+        return Ok(next.run(request).await);
+    };
+    let key = key
+        .to_str()
+        .ok()
+        .map(str::trim)
+        .filter(|key| !key.is_empty() && key.len() <= MAX_KEY_LEN)
+        .ok_or(ApiErrorResponse::InvalidIdempotencyKey)?
+        .to_string();
+    // The same key sent to another route is another request altogether
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or_else(|| request.uri().path(), MatchedPath::as_str);
+    let scope = format!("{} {}", request.method(), path);
+
+    let (parts, body) = request.into_parts();
+    let body =
+        to_bytes(body, MAX_BODY_SIZE).await.map_err(|_| ApiErrorResponse::PayloadTooLarge)?;
+    let fingerprint = format!("{:x}", Sha256::digest(&body));
+
+    match state.idempotency_keys.reserve(&scope, &key, &fingerprint).await? {
+        Reservation::Reserved => {}
+        Reservation::InFlight => return Err(ApiErrorResponse::IdempotencyKeyInFlight),
+        Reservation::Mismatch => return Err(ApiErrorResponse::IdempotencyKeyMismatch),
+        Reservation::Completed(stored) => {
+            tracing::info!("idempotent request replayed");
+            return Ok(replay(stored));
+        }
+    }
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+
+    // Only a success is final, any failure may be retried with the same key
+    if !response.status().is_success() {
+        state.idempotency_keys.release(&scope, &key).await?;
+        return Ok(response);
+    }
+
+    let (parts, body) = response.into_parts();
+    let body =
+        to_bytes(body, MAX_BODY_SIZE).await.map_err(|_| ApiErrorResponse::PayloadTooLarge)?;
+    let stored = StoredResponse {
+        status: parts.status.as_u16(),
+        content_type: parts
+            .headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .map(str::to_string),
+        body: String::from_utf8_lossy(&body).into_owned(),
+    };
+    state.idempotency_keys.complete(&scope, &key, stored).await?;
+
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+fn replay(stored: StoredResponse) -> Response {
+    let status = StatusCode::from_u16(stored.status).unwrap_or(StatusCode::OK);
+    let mut response = (
+        status,
+        [(IDEMPOTENT_REPLAYED, HeaderValue::from_static("true"))],
+        stored.body,
+    )
+        .into_response();
+
+    // A `String` body goes out as plain text, unless the stored type says otherwise
+    response.headers_mut().remove(header::CONTENT_TYPE);
+    if let Some(content_type) =
+        stored.content_type.and_then(|content_type| HeaderValue::try_from(content_type).ok())
+    {
+        response.headers_mut().insert(header::CONTENT_TYPE, content_type);
+    }
+
+    response
+}
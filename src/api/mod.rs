@@ -1,5 +1,9 @@
+pub mod admin;
+pub mod auth;
 pub mod errors;
 pub mod handlers;
+pub mod idempotency;
 pub mod models;
+pub mod rate_limit;
 pub mod requests;
 pub mod responses;
@@ -1,36 +1,86 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use validator::{Validate, ValidationError};
 
-#[derive(Debug, Deserialize, Validate)]
+use super::requests::{Honeypot, Localized};
+
+#[derive(Debug, Deserialize, Serialize, Validate)]
 #[serde(rename_all = "camelCase", deny_unknown_fields)]
 #[validate(schema(function = "validate_budget_bounds"))]
 #[must_use]
 pub struct LetsStartForm {
-    #[validate(email(message = "The @mail must be a valid email address"))]
+    #[validate(email(
+        code = "email_invalid",
+        message = "The @mail must be a valid email address"
+    ))]
     pub email: String,
 
     #[validate(range(
         min = 1_000,
         exclusive_max = 50_000,
+        code = "budget_range",
         message = "The budget must range from 1,000 to 50,000 USD"
     ))]
     pub min_budget: u16,
     #[validate(range(
         min = 1_000,
         max = 50_000,
+        code = "budget_range",
         message = "The budget must range from 1,000 to 50,000 USD"
     ))]
     pub max_budget: u16,
 
-    #[validate(length(min = 2, max = 32, message = "The name must be between 2 and 32 chars"))]
+    #[validate(length(
+        min = 2,
+        max = 32,
+        code = "name_length",
+        message = "The name must be between 2 and 32 chars"
+    ))]
     pub name: String,
 
     #[validate(length(
         min = 64,
         max = 512,
+        code = "project_description_length",
         message = "The project description must be between 64 and 512 chars"
     ))]
     pub project_description: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(custom(function = "validate_locale"))]
+    pub locale: Option<String>,
+
+    /// Single-use, so it's verified once and never queued along with the form
+    #[serde(default, skip_serializing)]
+    #[validate(length(
+        max = 4096,
+        code = "captcha_invalid",
+        message = "The captcha couldn't be verified, please try again"
+    ))]
+    pub captcha_token: Option<String>,
+
+    /// Issued by `GET /api/v1/form-token` when the form is rendered
+    #[serde(default, skip_serializing)]
+    #[validate(length(
+        max = 256,
+        code = "form_token_invalid",
+        message = "The form is outdated, please reload the page and try again"
+    ))]
+    pub form_token: Option<String>,
+
+    #[serde(skip)]
+    pub honeypot_filled: bool,
+}
+
+impl Localized for LetsStartForm {
+    fn locale(&self) -> Option<&str> {
+        self.locale.as_deref()
+    }
+}
+
+impl Honeypot for LetsStartForm {
+    fn set_honeypot_filled(&mut self, filled: bool) {
+        self.honeypot_filled = filled;
+    }
 }
 
 fn validate_budget_bounds(form: &LetsStartForm) -> Result<(), ValidationError> {
@@ -42,3 +92,15 @@ fn validate_budget_bounds(form: &LetsStartForm) -> Result<(), ValidationError> {
 
     Ok(())
 }
+
+fn validate_locale(locale: &str) -> Result<(), ValidationError> {
+    let is_valid = (2..=35).contains(&locale.len())
+        && locale.chars().all(|char| char.is_ascii_alphanumeric() || char == '-' || char == '_');
+    if !is_valid {
+        let mut err = ValidationError::new("locale_invalid");
+        err.message = Some("The locale must be a language tag like en or de-AT".into());
+        return Err(err);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,41 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::Response,
+};
+
+use super::errors::ApiErrorResponse;
+use crate::{AppState, services::rate_limit::ip_key};
+
+/// Address of the client behind the trusted proxies, if any
+#[derive(Clone, Copy, Debug)]
+pub struct ClientIp(pub IpAddr);
+
+/// Lets the request through while its client IP has tokens left
+pub async fn ip_rate_limit(
+    State(state): State<Arc<AppState>>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, ApiErrorResponse> {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |ConnectInfo(addr)| {
+            addr.ip()
+        });
+    let client_ip = state.trusted_proxies.client_ip(peer, request.headers());
+
+    state.ip_rate_limiter.check(&ip_key(client_ip)).map_err(|retry_after| {
+        tracing::warn!("rate limit exceeded by {client_ip}");
+        ApiErrorResponse::TooManyRequests(retry_after)
+    })?;
+
+    request.extensions_mut().insert(ClientIp(client_ip));
+
+    Ok(next.run(request).await)
+}
@@ -1,11 +1,30 @@
 use axum::{
     Json,
-    extract::{FromRequest, Request, rejection::JsonRejection},
+    extract::{FromRef, FromRequest, FromRequestParts, Path, Query, Request},
+    http::{HeaderMap, header, request::Parts},
 };
 use serde::de::DeserializeOwned;
+use serde_json::Value;
 use validator::Validate;
 
-use super::errors::ApiErrorResponse;
+use super::errors::{ApiErrorResponse, localize_validation_errors};
+use crate::services::i18n::I18n;
+
+/// Payloads that may carry the locale their author prefers
+pub trait Localized {
+    fn locale(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// Payloads that remember whether the hidden honeypot field came filled in
+pub trait Honeypot {
+    fn set_honeypot_filled(&mut self, _filled: bool) {}
+}
+
+/// Name of the honeypot field, taken out before the payload is deserialized
+#[derive(Clone, Debug, Default)]
+pub struct HoneypotField(pub Option<String>);
 
 #[cfg_attr(debug_assertions, derive(Debug))]
 #[derive(Default, Copy, Clone)]
@@ -15,17 +34,113 @@ pub struct ApiJsonRequest<T>(pub T);
 impl<S, T> FromRequest<S> for ApiJsonRequest<T>
 where
     S: Send + Sync,
-    T: DeserializeOwned + Validate,
-    Json<T>: FromRequest<S, Rejection = JsonRejection>,
+    T: DeserializeOwned + Validate + Localized + Honeypot,
+    I18n: FromRef<S>,
+    HoneypotField: FromRef<S>,
 {
     type Rejection = ApiErrorResponse;
 
     async fn from_request(rq: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let ApiLocale(header_locale) = ApiLocale::from_headers(rq.headers(), state);
+
         // First, parse the JSON
-        let Json(payload) = Json::<T>::from_request(rq, state).await?;
-        // ... then validate
-        payload.validate()?;
+        let Json(mut body) = Json::<Value>::from_request(rq, state).await?;
+        // ... take the honeypot out, the payload denies the fields it doesn't know
+        let HoneypotField(honeypot) = HoneypotField::from_ref(state);
+        let honeypot = honeypot.and_then(|name| body.as_object_mut()?.remove(&name));
+        // ... map it onto the payload, keeping the paths in the errors
+        let mut payload: T = serde_path_to_error::deserialize(body)?;
+        payload.set_honeypot_filled(honeypot.is_some_and(|value| is_filled(&value)));
+        // ... then validate, speaking the language of the author
+        if let Err(mut errors) = payload.validate() {
+            let i18n = I18n::from_ref(state);
+            let locale =
+                i18n.negotiate(payload.locale().into_iter().chain([header_locale.as_str()]));
+            localize_validation_errors(&mut errors, &i18n, &locale);
+
+            return Err(errors.into());
+        }
 
         Ok(ApiJsonRequest(payload))
     }
 }
+
+/// Query string, rejected with the error shape of the API rather than axum's plain text
+#[cfg_attr(debug_assertions, derive(Debug))]
+#[derive(Default, Copy, Clone)]
+#[must_use]
+pub struct ApiQuery<T>(pub T);
+
+impl<S, T> FromRequestParts<S> for ApiQuery<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = ApiErrorResponse;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(query) = Query::<T>::from_request_parts(parts, state).await?;
+
+        Ok(ApiQuery(query))
+    }
+}
+
+/// Path parameters, rejected with the error shape of the API rather than axum's plain text
+#[cfg_attr(debug_assertions, derive(Debug))]
+#[derive(Default, Copy, Clone)]
+#[must_use]
+pub struct ApiPath<T>(pub T);
+
+impl<S, T> FromRequestParts<S> for ApiPath<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Send,
+{
+    type Rejection = ApiErrorResponse;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(path) = Path::<T>::from_request_parts(parts, state).await?;
+
+        Ok(ApiPath(path))
+    }
+}
+
+fn is_filled(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::String(text) => !text.trim().is_empty(),
+        _ => true,
+    }
+}
+
+/// Locale negotiated from the `Accept-Language` header
+#[derive(Clone, Debug)]
+pub struct ApiLocale(pub String);
+
+impl ApiLocale {
+    fn from_headers<S>(headers: &HeaderMap, state: &S) -> Self
+    where
+        I18n: FromRef<S>,
+    {
+        let i18n = I18n::from_ref(state);
+        let locale =
+            headers.get(header::ACCEPT_LANGUAGE).and_then(|value| value.to_str().ok()).map_or_else(
+                || i18n.default_locale().to_string(),
+                |value| i18n.negotiate_accept_language(value),
+            );
+
+        Self(locale)
+    }
+}
+
+impl<S> FromRequestParts<S> for ApiLocale
+where
+    S: Send + Sync,
+    I18n: FromRef<S>,
+{
+    type Rejection = ApiErrorResponse;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::from_headers(&parts.headers, state))
+    }
+}
@@ -19,14 +19,24 @@ pub struct ApiJsonResponse<T = ApiMessage> {
 pub struct ApiMeta {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pagination: Option<ApiPagination>,
 }
 
 impl ApiMeta {
     pub fn with_message(message: impl Into<String>) -> Self {
-        Self { message: Some(message.into()) }
+        Self { message: Some(message.into()), pagination: None }
     }
 }
 
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiPagination {
+    pub limit: u32,
+    pub offset: u32,
+    pub total: u64,
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ApiMessage {
@@ -39,10 +49,21 @@ impl ApiMessage {
     }
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiFormToken {
+    pub token: String,
+}
+
 impl<T> ApiJsonResponse<T> {
     pub fn with_data(data: T) -> Self {
         Self { data: Some(data), meta: None, errors: None }
     }
+
+    pub fn paginated(data: T, pagination: ApiPagination) -> Self {
+        let meta = ApiMeta { message: None, pagination: Some(pagination) };
+        Self { data: Some(data), meta: Some(meta), errors: None }
+    }
 }
 
 impl ApiJsonResponse<ApiMessage> {
@@ -0,0 +1,312 @@
+//! A tiny admin CLI, a thin client over the `/api/v1/admin` endpoints
+
+use std::path::PathBuf;
+
+use anyhow::{Context, bail};
+use clap::{Parser, Subcommand, ValueEnum};
+use reqwest::{Client, Method, Response};
+use serde_json::Value;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+#[derive(Debug, Parser)]
+#[command(name = "lets-start-admin", version, about)]
+struct Cli {
+    /// Base URL of the running service
+    #[arg(long, env = "LETS_START_URL", default_value = "http://localhost:8000")]
+    url: String,
+
+    /// One of the `admin_tokens` from the service secrets
+    #[arg(long, env = "LETS_START_ADMIN_TOKEN", hide_env_values = true)]
+    token: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Inspect and replay undeliverable messages
+    #[command(subcommand)]
+    DeadLetters(DeadLettersCommand),
+    /// Inspect and release messages held back by the spam filter
+    #[command(subcommand)]
+    Quarantine(QuarantineCommand),
+    /// Browse the stored leads
+    #[command(subcommand)]
+    Submissions(SubmissionsCommand),
+    /// Erase everything stored for an email address
+    Erase {
+        email: String,
+        /// Kept in the audit trail, e.g. the ticket of the request
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// Inspect the audit trail of the erasures
+    #[command(subcommand)]
+    Erasures(ErasuresCommand),
+    /// Re-encrypt the stored submissions with the active encryption key
+    RotateKeys,
+}
+
+#[derive(Debug, Subcommand)]
+enum ErasuresCommand {
+    /// List erasures, newest first
+    List {
+        #[arg(long, default_value_t = 50)]
+        limit: u32,
+        #[arg(long, default_value_t = 0)]
+        offset: u32,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum DeadLettersCommand {
+    /// List dead letters, newest first
+    List {
+        #[arg(long, default_value_t = 50)]
+        limit: u32,
+        #[arg(long, default_value_t = 0)]
+        offset: u32,
+    },
+    /// Show a single dead letter with its payload
+    Show { id: i64 },
+    /// Put a dead letter back into the outbox
+    Replay { id: i64 },
+}
+
+#[derive(Debug, Subcommand)]
+enum QuarantineCommand {
+    /// List quarantined messages, newest first
+    List {
+        #[arg(long, default_value_t = 50)]
+        limit: u32,
+        #[arg(long, default_value_t = 0)]
+        offset: u32,
+    },
+    /// Show a single quarantined message with its score
+    Show { id: i64 },
+    /// Let a quarantined message through to the outbox
+    Release { id: i64 },
+}
+
+#[derive(Debug, Subcommand)]
+enum SubmissionsCommand {
+    /// List submissions, newest first
+    List {
+        #[arg(long, default_value_t = 50)]
+        limit: u32,
+        #[arg(long, default_value_t = 0)]
+        offset: u32,
+        #[command(flatten)]
+        filter: SubmissionFilter,
+    },
+    /// Show a single submission with its payload
+    Show { id: i64 },
+    /// Stream the matching submissions, oldest first
+    Export {
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+        /// Written to stdout when omitted
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        #[command(flatten)]
+        filter: SubmissionFilter,
+    },
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ExportFormat {
+    Csv,
+    Ndjson,
+}
+
+impl ExportFormat {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Ndjson => "ndjson",
+        }
+    }
+}
+
+#[derive(Debug, clap::Args)]
+struct SubmissionFilter {
+    /// One of: received, quarantined, sent, failed
+    #[arg(long)]
+    status: Option<String>,
+    /// Received at or after, RFC 3339
+    #[arg(long)]
+    from: Option<String>,
+    /// Received before, RFC 3339
+    #[arg(long)]
+    to: Option<String>,
+    /// Budget ranges reaching up to at least this much
+    #[arg(long)]
+    min_budget: Option<u16>,
+    /// Budget ranges starting at this much at most
+    #[arg(long)]
+    max_budget: Option<u16>,
+    /// Either the Origin header or the client IP
+    #[arg(long)]
+    origin: Option<String>,
+}
+
+impl SubmissionFilter {
+    fn query(&self) -> String {
+        let min_budget = self.min_budget.map(|budget| budget.to_string());
+        let max_budget = self.max_budget.map(|budget| budget.to_string());
+        let params = [
+            ("status", self.status.as_deref()),
+            ("from", self.from.as_deref()),
+            ("to", self.to.as_deref()),
+            ("minBudget", min_budget.as_deref()),
+            ("maxBudget", max_budget.as_deref()),
+            ("origin", self.origin.as_deref()),
+        ];
+
+        params
+            .iter()
+            .filter_map(|(key, value)| {
+                value.map(|value| format!("&{key}={}", urlencoding::encode(value)))
+            })
+            .collect()
+    }
+}
+
+struct AdminClient {
+    client: Client,
+    url: String,
+    token: String,
+}
+
+impl AdminClient {
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Value>,
+    ) -> anyhow::Result<Response> {
+        let url = format!("{}/api/v1/admin{path}", self.url.trim_end_matches('/'));
+
+        let mut request = self.client.request(method, url).bearer_auth(&self.token);
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+
+        request.send().await.context("couldn't reach the service")
+    }
+
+    async fn json(&self, method: Method, path: &str) -> anyhow::Result<Value> {
+        self.json_with(method, path, None).await
+    }
+
+    async fn json_with(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Value>,
+    ) -> anyhow::Result<Value> {
+        let response = self.request(method, path, body).await?;
+        let status = response.status();
+        let body: Value = response.json().await.context("couldn't parse the response")?;
+
+        if !status.is_success() {
+            let message = body["meta"]["message"].as_str().unwrap_or("unknown error");
+            bail!("{status}: {message}");
+        }
+
+        Ok(body)
+    }
+
+    /// Copies the response body chunk by chunk, never holding all of it
+    async fn download(
+        &self,
+        path: &str,
+        output: &mut (impl AsyncWrite + Unpin),
+    ) -> anyhow::Result<()> {
+        let mut response = self.request(Method::GET, path, None).await?;
+        let status = response.status();
+
+        if !status.is_success() {
+            let body: Value = response.json().await.unwrap_or_default();
+            let message = body["meta"]["message"].as_str().unwrap_or("unknown error");
+            bail!("{status}: {message}");
+        }
+
+        while let Some(chunk) = response.chunk().await.context("couldn't read the response")? {
+            output.write_all(&chunk).await.context("couldn't write the export")?;
+        }
+        output.flush().await.context("couldn't write the export")?;
+
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let admin = AdminClient { client: Client::new(), url: cli.url, token: cli.token };
+
+    let body = match cli.command {
+        Command::DeadLetters(DeadLettersCommand::List { limit, offset }) => {
+            let path = format!("/dead-letters?limit={limit}&offset={offset}");
+            admin.json(Method::GET, &path).await?
+        }
+        Command::DeadLetters(DeadLettersCommand::Show { id }) => {
+            admin.json(Method::GET, &format!("/dead-letters/{id}")).await?
+        }
+        Command::DeadLetters(DeadLettersCommand::Replay { id }) => {
+            admin.json(Method::POST, &format!("/dead-letters/{id}/replay")).await?
+        }
+        Command::Quarantine(QuarantineCommand::List { limit, offset }) => {
+            let path = format!("/quarantine?limit={limit}&offset={offset}");
+            admin.json(Method::GET, &path).await?
+        }
+        Command::Quarantine(QuarantineCommand::Show { id }) => {
+            admin.json(Method::GET, &format!("/quarantine/{id}")).await?
+        }
+        Command::Quarantine(QuarantineCommand::Release { id }) => {
+            admin.json(Method::POST, &format!("/quarantine/{id}/release")).await?
+        }
+        Command::Submissions(SubmissionsCommand::List { limit, offset, filter }) => {
+            let path = format!(
+                "/submissions?limit={limit}&offset={offset}{}",
+                filter.query()
+            );
+            admin.json(Method::GET, &path).await?
+        }
+        Command::Submissions(SubmissionsCommand::Show { id }) => {
+            admin.json(Method::GET, &format!("/submissions/{id}")).await?
+        }
+        Command::Erase { email, reason } => {
+            let body = serde_json::json!({ "email": email, "reason": reason });
+            admin.json_with(Method::POST, "/erasures", Some(&body)).await?
+        }
+        Command::Erasures(ErasuresCommand::List { limit, offset }) => {
+            let path = format!("/erasures?limit={limit}&offset={offset}");
+            admin.json(Method::GET, &path).await?
+        }
+        Command::RotateKeys => admin.json(Method::POST, "/encryption/rotate").await?,
+        Command::Submissions(SubmissionsCommand::Export { format, output, filter }) => {
+            let path = format!(
+                "/submissions/export?format={}{}",
+                format.as_str(),
+                filter.query()
+            );
+            match output {
+                Some(output) => {
+                    let mut file = tokio::fs::File::create(&output)
+                        .await
+                        .with_context(|| format!("couldn't create {}", output.display()))?;
+                    admin.download(&path, &mut file).await?;
+                }
+                None => admin.download(&path, &mut tokio::io::stdout()).await?,
+            }
+            return Ok(());
+        }
+    };
+
+    println!("{}", serde_json::to_string_pretty(&body["data"])?);
+
+    Ok(())
+}
@@ -1,29 +1,111 @@
-use std::convert::TryFrom;
+use std::{collections::HashSet, convert::TryFrom, str::FromStr};
 
+#[cfg(feature = "shuttle")]
+use anyhow::bail;
 use anyhow::{Context, Result};
+#[cfg(feature = "standalone")]
+use config::Environment;
 use config::{Config, File};
+use lettre::message::Mailbox;
 use sentry::types::Dsn;
 use serde::Deserialize;
+#[cfg(feature = "shuttle")]
 use shuttle_runtime::SecretStore;
 use validator::{Validate, ValidationError};
 
-use crate::cors::validate_allow_origin_entry;
+use crate::{
+    cors::validate_allow_origin_entry,
+    services::{
+        captcha::CaptchaProviderKind, domain_policy::validate_domain_pattern,
+        duplicates::DuplicateAction, encryption::parse_key, privacy::RetentionAction,
+        rate_limit::parse_net, transport::MailTransportKind,
+    },
+};
 
 #[derive(Clone, Debug, Default, Deserialize, Validate)]
+#[validate(schema(function = "validate_mail_transport"))]
+#[validate(schema(function = "validate_captcha_provider"))]
+#[validate(schema(function = "validate_spam_scores"))]
+#[validate(schema(function = "validate_encryption"))]
+#[validate(schema(function = "validate_webhook_names"))]
 #[must_use]
 pub struct AppConfigs {
     #[validate(length(min = 1, message = "must be at least one of the allowed origins"))]
     #[validate(custom(function = "validate_allow_origins_urls"))]
     pub(super) allow_cors_origins: Vec<String>,
 
+    #[validate(custom(function = "validate_mailbox"))]
     pub(super) from_mailbox: String,
-    pub(super) to_mailbox: String,
+    #[validate(length(min = 1, message = "must be at least one of the recipients"))]
+    #[validate(custom(function = "validate_mailboxes"))]
+    pub(super) to_mailboxes: Vec<String>,
+    #[serde(default)]
+    #[validate(custom(function = "validate_mailboxes"))]
+    pub(super) cc_mailboxes: Vec<String>,
+    #[serde(default)]
+    #[validate(custom(function = "validate_mailboxes"))]
+    pub(super) bcc_mailboxes: Vec<String>,
+
+    #[serde(default)]
+    #[validate(nested)]
+    pub(super) routing_rules: Vec<RoutingRule>,
+
+    #[serde(default)]
+    #[validate(nested)]
+    pub(super) webhooks: Vec<Webhook>,
+
+    pub(super) locales_dir: String,
+    #[validate(length(min = 2, message = "must be a language tag like en"))]
+    pub(super) default_locale: String,
+
+    pub(super) templates_dir: String,
+    pub(super) templates_hot_reload: bool,
+    #[validate(length(min = 1, max = 256, message = "must be between 1 and 256 chars"))]
+    pub(super) subject_template: String,
+
+    pub auto_reply_enabled: bool,
+    #[validate(length(min = 1, max = 128, message = "must be between 1 and 128 chars"))]
+    pub auto_reply_subject: String,
 
     #[validate(range(min = 1, max = 10, message = "must be between 1 and 10 times"))]
     pub retry_count: usize,
     #[validate(range(min = 10, max = 100, message = "must be between 10 and 100 msec"))]
     pub retry_timeout: u64,
 
+    pub(super) database_path: String,
+
+    #[validate(range(min = 1, max = 32, message = "must be between 1 and 32 times"))]
+    pub outbox_retry_count: u32,
+    #[validate(range(min = 1, max = 3600, message = "must be between 1 and 3600 sec"))]
+    pub outbox_retry_timeout: u64,
+    #[validate(range(min = 1, max = 86400, message = "must be between 1 and 86400 sec"))]
+    pub outbox_retry_max_timeout: u64,
+    #[validate(range(min = 60, max = 604800, message = "must be between 60 and 604800 sec"))]
+    pub(super) idempotency_ttl: u64,
+
+    #[validate(range(max = 604800, message = "must be between 0 and 604800 sec"))]
+    pub(super) duplicate_window: u64,
+    pub(super) duplicate_action: DuplicateAction,
+
+    #[serde(default)]
+    #[validate(custom(function = "validate_encryption_keys"))]
+    pub(super) encryption_keys: Vec<String>,
+    #[serde(default)]
+    pub(super) encryption_key_id: Option<String>,
+    #[serde(default)]
+    #[validate(length(min = 32, message = "must be at least 32 chars"))]
+    pub(super) email_index_secret: Option<String>,
+
+    #[validate(range(max = 3650, message = "must be between 0 and 3650 days"))]
+    pub(super) retention_days: u64,
+    pub(super) retention_action: RetentionAction,
+    #[validate(range(min = 60, max = 86400, message = "must be between 60 and 86400 sec"))]
+    pub(super) retention_interval: u64,
+
+    #[serde(default)]
+    #[validate(custom(function = "validate_admin_tokens"))]
+    pub(super) admin_tokens: Vec<String>,
+
     #[validate(custom(function = "validate_sentry_dsn"))]
     pub(super) sentry_dsn: String,
     pub(super) sentry_environment: String,
@@ -34,20 +116,196 @@ pub struct AppConfigs {
         message = "must be between 1 and 1024 concurrent requests"
     ))]
     pub(super) concurrency_limit: usize,
+    #[validate(range(min = 1, max = 300, message = "must be between 1 and 300 sec"))]
+    pub(super) shutdown_timeout: u64,
+
+    #[validate(custom(function = "validate_trusted_proxies"))]
+    pub(super) trusted_proxies: Vec<String>,
+    #[validate(range(min = 1, max = 1000, message = "must be between 1 and 1000 requests"))]
+    pub(super) ip_rate_limit_burst: u32,
+    #[validate(range(min = 1, max = 86400, message = "must be between 1 and 86400 sec"))]
+    pub(super) ip_rate_limit_refill: u64,
+    #[validate(range(min = 1, max = 1000, message = "must be between 1 and 1000 requests"))]
+    pub(super) email_rate_limit_burst: u32,
+    #[validate(range(min = 1, max = 86400, message = "must be between 1 and 86400 sec"))]
+    pub(super) email_rate_limit_refill: u64,
+
+    pub(super) captcha_provider: CaptchaProviderKind,
+    #[serde(default)]
+    pub(super) captcha_secret: Option<String>,
+    #[serde(default)]
+    #[validate(url(message = "must be a valid URL"))]
+    pub(super) captcha_verify_url: Option<String>,
+    #[validate(range(min = 1000, max = 30000, message = "must be between 1000 and 30000 msec"))]
+    pub(super) captcha_timeout: u64,
+
+    pub(super) block_disposable_domains: bool,
+    #[validate(custom(function = "validate_domain_patterns"))]
+    pub(super) email_domain_allowlist: Vec<String>,
+    #[validate(custom(function = "validate_domain_patterns"))]
+    pub(super) email_domain_denylist: Vec<String>,
+
+    #[validate(range(max = 100, message = "must be between 0 and 100 links"))]
+    pub(super) spam_max_links: usize,
+    #[validate(range(min = 0.0, message = "must not be negative"))]
+    pub(super) spam_link_score: f64,
+    pub(super) spam_keywords: Vec<String>,
+    #[validate(range(min = 0.0, message = "must not be negative"))]
+    pub(super) spam_keyword_score: f64,
+    #[validate(range(min = 0.0, message = "must not be negative"))]
+    pub(super) spam_disposable_score: f64,
+    #[validate(range(min = 2, max = 64, message = "must be between 2 and 64 chars"))]
+    pub(super) spam_max_repeated_chars: usize,
+    #[validate(range(min = 0.0, message = "must not be negative"))]
+    pub(super) spam_repeated_chars_score: f64,
+    pub(super) spam_tag_score: f64,
+    pub(super) spam_quarantine_score: f64,
+    pub(super) spam_reject_score: f64,
+
+    #[serde(default)]
+    #[validate(custom(function = "validate_honeypot_field"))]
+    pub(super) honeypot_field: Option<String>,
+    #[serde(default)]
+    #[validate(length(min = 32, message = "must be at least 32 chars"))]
+    pub(super) form_token_secret: Option<String>,
+    #[validate(range(max = 3600, message = "must be between 0 and 3600 sec"))]
+    pub(super) form_token_min_age: u64,
+    #[validate(range(min = 60, max = 604800, message = "must be between 60 and 604800 sec"))]
+    pub(super) form_token_max_age: u64,
+
+    #[cfg(feature = "standalone")]
+    #[validate(custom(function = "validate_listen_addr"))]
+    pub(super) listen_addr: String,
+
+    pub(super) mail_transport: MailTransportKind,
+    #[serde(default)]
+    pub(super) mail_drop_dir: Option<String>,
 
+    #[serde(default)]
     #[validate(custom(function = "validate_smtp_addr"))]
-    pub(super) smtp_addr: String,
+    pub(super) smtp_addr: Option<String>,
+    #[serde(default)]
     #[validate(custom(function = "validate_smtp_auth_uri"))]
-    pub(super) smtp_auth: String,
+    pub(super) smtp_auth: Option<String>,
     #[validate(range(min = 1000, message = "must be at least 1000 msec"))]
     pub(super) smtp_connection_timeout: u64,
 }
 
+/// Adds recipients to the lead email when all of the given conditions hold
+#[derive(Clone, Debug, Default, Deserialize, Validate)]
+#[validate(schema(function = "validate_routing_rule"))]
+pub struct RoutingRule {
+    #[validate(length(min = 1, message = "must be a non-empty rule name"))]
+    pub name: String,
+
+    pub min_budget_at_least: Option<u16>,
+    pub max_budget_at_least: Option<u16>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+
+    #[serde(default)]
+    #[validate(custom(function = "validate_mailboxes"))]
+    pub to: Vec<String>,
+    #[serde(default)]
+    #[validate(custom(function = "validate_mailboxes"))]
+    pub cc: Vec<String>,
+    #[serde(default)]
+    #[validate(custom(function = "validate_mailboxes"))]
+    pub bcc: Vec<String>,
+}
+
+/// Receives every accepted submission as a signed JSON POST
+#[derive(Clone, Debug, Default, Deserialize, Validate)]
+pub struct Webhook {
+    #[validate(length(min = 1, message = "must be a non-empty webhook name"))]
+    pub name: String,
+    #[validate(url(message = "must be a valid URL"))]
+    pub url: String,
+    #[validate(length(min = 32, message = "must be at least 32 chars"))]
+    pub secret: String,
+
+    #[serde(default = "default_webhook_retry_count")]
+    #[validate(range(max = 10, message = "must be between 0 and 10 times"))]
+    pub retry_count: usize,
+    #[serde(default = "default_webhook_retry_timeout")]
+    #[validate(range(min = 100, max = 60000, message = "must be between 100 and 60000 msec"))]
+    pub retry_timeout: u64,
+    #[serde(default = "default_webhook_timeout")]
+    #[validate(range(min = 1000, max = 30000, message = "must be between 1000 and 30000 msec"))]
+    pub timeout: u64,
+}
+
+fn default_webhook_retry_count() -> usize {
+    3
+}
+
+fn default_webhook_retry_timeout() -> u64 {
+    1000
+}
+
+fn default_webhook_timeout() -> u64 {
+    5000
+}
+
+/// Keys of the environment variables, which hold comma-separated lists
+#[cfg(feature = "standalone")]
+const ENV_LIST_KEYS: &[&str] = &[
+    "admin_tokens",
+    "allow_cors_origins",
+    "bcc_mailboxes",
+    "cc_mailboxes",
+    "email_domain_allowlist",
+    "email_domain_denylist",
+    "encryption_keys",
+    "spam_keywords",
+    "to_mailboxes",
+    "trusted_proxies",
+];
+
 impl AppConfigs {
-    pub fn new(secrets: SecretStore) -> Result<Self> {
+    /// Loads the configs from the Shuttle secret store
+    #[cfg(feature = "shuttle")]
+    pub fn from_shuttle(secrets: SecretStore) -> Result<Self> {
         let secrets_source =
             Config::try_from(&secrets).context("couldn't get the secrets from the secret store")?;
 
+        let configs = Self::new(secrets_source)?;
+        // Every request reaches the service through the Shuttle proxy, so without trusting it
+        // all the clients would share the IP rate limit of the proxy
+        if configs.trusted_proxies.is_empty() {
+            tracing::error!("no trusted_proxies behind the Shuttle proxy");
+            bail!("trusted_proxies must name the Shuttle proxy");
+        }
+
+        Ok(configs)
+    }
+
+    /// Loads the configs from the secrets file (if any) and the `LETS_START_*` env variables
+    #[cfg(feature = "standalone")]
+    pub fn from_env() -> Result<Self> {
+        let secrets_path =
+            std::env::var("LETS_START_SECRETS").unwrap_or_else(|_| "Secrets.toml".to_string());
+
+        let mut environment = Environment::with_prefix("LETS_START")
+            .prefix_separator("_")
+            .try_parsing(true)
+            .list_separator(",");
+        for key in ENV_LIST_KEYS {
+            environment = environment.with_list_parse_key(key);
+        }
+
+        let secrets_source = Config::builder()
+            .add_source(File::with_name(&secrets_path).required(false))
+            .add_source(environment)
+            .build()
+            .inspect_err(|_| tracing::error!("secrets error (sanitized)"))
+            .context("couldn't get the secrets from the file or the environment")?;
+
+        Self::new(secrets_source)
+    }
+
+    /// Layers the secrets on top of the `configs/default.toml`
+    pub fn new(secrets_source: Config) -> Result<Self> {
         let configs: Self = Config::builder()
             .add_source(File::with_name("configs/default").required(true))
             .add_source(secrets_source)
@@ -76,6 +334,27 @@ impl TryFrom<Config> for AppConfigs {
     }
 }
 
+#[cfg(test)]
+impl AppConfigs {
+    /// The defaults with the required secrets filled in, then the overrides on top
+    pub fn for_tests(overrides: &[(&str, &str)]) -> Self {
+        let mut builder = Config::builder()
+            .set_override("allow_cors_origins", vec!["http://localhost:3000"])
+            .and_then(|builder| {
+                builder.set_override("sentry_dsn", "https://key@o0.ingest.sentry.io/0")
+            })
+            .and_then(|builder| builder.set_override("sentry_environment", "test"))
+            .and_then(|builder| builder.set_override("mail_transport", "memory"))
+            .and_then(|builder| builder.set_override("database_path", ":memory:"))
+            .expect("the test secrets are valid");
+        for (key, value) in overrides {
+            builder = builder.set_override(*key, *value).expect("the test overrides are valid");
+        }
+
+        Self::new(builder.build().expect("the test configs build")).expect("the test configs load")
+    }
+}
+
 fn validate_allow_origins_urls(origins: &[String]) -> Result<(), ValidationError> {
     for origin in origins {
         validate_allow_origin_entry(origin)?;
@@ -84,6 +363,185 @@ fn validate_allow_origins_urls(origins: &[String]) -> Result<(), ValidationError
     Ok(())
 }
 
+fn validate_mail_transport(configs: &AppConfigs) -> Result<(), ValidationError> {
+    let is_missing = |value: &Option<String>| value.as_deref().is_none_or(str::is_empty);
+
+    match configs.mail_transport {
+        MailTransportKind::Smtp
+            if is_missing(&configs.smtp_addr) || is_missing(&configs.smtp_auth) =>
+        {
+            let mut err = ValidationError::new("invalid_mail_transport");
+            err.message = Some("smtp transport requires smtp_addr and smtp_auth".into());
+            Err(err)
+        }
+        MailTransportKind::File if is_missing(&configs.mail_drop_dir) => {
+            let mut err = ValidationError::new("invalid_mail_transport");
+            err.message = Some("file transport requires mail_drop_dir".into());
+            Err(err)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn validate_domain_patterns(patterns: &[String]) -> Result<(), ValidationError> {
+    for pattern in patterns {
+        validate_domain_pattern(pattern)?;
+    }
+
+    Ok(())
+}
+
+fn validate_spam_scores(configs: &AppConfigs) -> Result<(), ValidationError> {
+    let is_ordered = 0.0 < configs.spam_tag_score
+        && configs.spam_tag_score <= configs.spam_quarantine_score
+        && configs.spam_quarantine_score <= configs.spam_reject_score;
+    if !is_ordered {
+        let mut err = ValidationError::new("invalid_spam_scores");
+        err.message = Some("spam scores must be 0 < tag <= quarantine <= reject".into());
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+fn validate_encryption_keys(keys: &[String]) -> Result<(), ValidationError> {
+    if keys.iter().any(|key| parse_key(key).is_err()) {
+        let mut err = ValidationError::new("invalid_encryption_key");
+        err.message = Some("every encryption key must look like <key id>:<base64 32 bytes>".into());
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+fn validate_encryption(configs: &AppConfigs) -> Result<(), ValidationError> {
+    if configs.encryption_keys.is_empty() {
+        return Ok(());
+    }
+
+    if configs.email_index_secret.is_none() {
+        let mut err = ValidationError::new("invalid_encryption");
+        err.message = Some("encryption_keys require email_index_secret".into());
+        return Err(err);
+    }
+
+    let is_known = |id: &String| {
+        configs
+            .encryption_keys
+            .iter()
+            .any(|key| key.split_once(':').is_some_and(|(key_id, _)| key_id == id))
+    };
+    if configs.encryption_key_id.as_ref().is_some_and(|id| !is_known(id)) {
+        let mut err = ValidationError::new("invalid_encryption");
+        err.message = Some("encryption_key_id must be one of the encryption_keys".into());
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+fn validate_captcha_provider(configs: &AppConfigs) -> Result<(), ValidationError> {
+    if configs.captcha_provider != CaptchaProviderKind::None
+        && configs.captcha_secret.as_deref().is_none_or(str::is_empty)
+    {
+        let mut err = ValidationError::new("invalid_captcha_provider");
+        err.message = Some("captcha provider requires captcha_secret".into());
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+fn validate_honeypot_field(field: &str) -> Result<(), ValidationError> {
+    const FORM_FIELDS: &[&str] = &[
+        "captchaToken",
+        "email",
+        "formToken",
+        "locale",
+        "maxBudget",
+        "minBudget",
+        "name",
+        "projectDescription",
+    ];
+
+    if field.trim().is_empty() || FORM_FIELDS.contains(&field) {
+        let mut err = ValidationError::new("invalid_honeypot_field");
+        err.message = Some("must be a non-empty name the form doesn't use".into());
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+fn validate_admin_tokens(tokens: &[String]) -> Result<(), ValidationError> {
+    if tokens.iter().any(|token| token.trim().len() < 32) {
+        let mut err = ValidationError::new("invalid_admin_token");
+        err.message = Some("every admin token must be at least 32 chars".into());
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+fn validate_webhook_names(configs: &AppConfigs) -> Result<(), ValidationError> {
+    // The queued deliveries find their webhook by the name
+    let names =
+        configs.webhooks.iter().map(|webhook| webhook.name.as_str()).collect::<HashSet<_>>();
+    if names.len() != configs.webhooks.len() {
+        let mut err = ValidationError::new("duplicate_webhook_name");
+        err.message = Some("every webhook must have a distinct name".into());
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+fn validate_trusted_proxies(proxies: &[String]) -> Result<(), ValidationError> {
+    if proxies.iter().any(|proxy| parse_net(proxy).is_err()) {
+        let mut err = ValidationError::new("invalid_trusted_proxy");
+        err.message = Some("every trusted proxy must be an IP or a CIDR like 10.0.0.0/8".into());
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+fn validate_mailbox(mailbox: &str) -> Result<(), ValidationError> {
+    Mailbox::from_str(mailbox).map_err(|_| {
+        let mut err = ValidationError::new("invalid_mailbox");
+        err.message = Some("must be a valid mailbox like \"Name <user@example.com>\"".into());
+        err
+    })?;
+
+    Ok(())
+}
+
+fn validate_mailboxes(mailboxes: &[String]) -> Result<(), ValidationError> {
+    for mailbox in mailboxes {
+        validate_mailbox(mailbox)?;
+    }
+
+    Ok(())
+}
+
+fn validate_routing_rule(rule: &RoutingRule) -> Result<(), ValidationError> {
+    if rule.min_budget_at_least.is_none()
+        && rule.max_budget_at_least.is_none()
+        && rule.keywords.is_empty()
+    {
+        let mut err = ValidationError::new("invalid_routing_rule");
+        err.message = Some("must have at least one condition".into());
+        return Err(err);
+    }
+    if rule.to.is_empty() && rule.cc.is_empty() && rule.bcc.is_empty() {
+        let mut err = ValidationError::new("invalid_routing_rule");
+        err.message = Some("must have at least one recipient".into());
+        return Err(err);
+    }
+
+    Ok(())
+}
+
 fn validate_sentry_dsn(dsn: &str) -> Result<(), ValidationError> {
     dsn.parse::<Dsn>().map_err(|_| {
         let mut err = ValidationError::new("invalid_sentry_dsn");
@@ -109,6 +567,17 @@ fn validate_smtp_auth_uri(auth: &str) -> Result<(), ValidationError> {
     Ok(())
 }
 
+#[cfg(feature = "standalone")]
+fn validate_listen_addr(addr: &str) -> Result<(), ValidationError> {
+    addr.parse::<std::net::SocketAddr>().map_err(|_| {
+        let mut err = ValidationError::new("invalid_listen_addr");
+        err.message = Some("must be ip:port".into());
+        err
+    })?;
+
+    Ok(())
+}
+
 fn validate_smtp_addr(addr: &str) -> Result<(), ValidationError> {
     let Some((host, port_str)) = addr.rsplit_once(":") else {
         let mut err = ValidationError::new("invalid_smtp_addr");
@@ -2,40 +2,117 @@ mod api;
 mod configs;
 mod cors;
 mod services;
+mod shutdown;
 
 use std::{
     borrow::Cow,
     sync::{Arc, OnceLock},
+    time::Duration,
 };
 
 use anyhow::Context;
 use axum::{
-    http::{HeaderValue, Method, header, request::Parts},
+    Router,
+    extract::FromRef,
+    http::{HeaderName, HeaderValue, Method, header, request::Parts},
+    middleware::from_fn_with_state,
     routing::{get, post},
 };
 use sentry::ClientInitGuard;
-use shuttle_axum::{ShuttleAxum, axum::Router as ShuttleRouter};
+#[cfg(feature = "shuttle")]
 use shuttle_runtime::{SecretStore as ShuttleSecretStore, Secrets as ShuttleSecrets};
 use tower::limit::ConcurrencyLimitLayer;
-use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::{
+    cors::{AllowOrigin, CorsLayer},
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+};
 use tracing_subscriber::{
     filter::{EnvFilter, LevelFilter},
     prelude::*,
 };
 
+#[cfg(feature = "shuttle")]
+use crate::shutdown::ShuttleService;
 use crate::{
-    api::handlers::{alive_handler, send_message_handler},
+    api::{
+        admin::{
+            erase_handler, export_submissions_handler, get_dead_letter_handler,
+            get_quarantined_handler, get_submission_handler, list_dead_letters_handler,
+            list_erasures_handler, list_quarantine_handler, list_submissions_handler,
+            release_quarantined_handler, replay_dead_letter_handler, rotate_keys_handler,
+        },
+        auth::admin_auth,
+        handlers::{alive_handler, form_token_handler, send_message_handler},
+        idempotency::{IDEMPOTENCY_KEY, IDEMPOTENT_REPLAYED, idempotency},
+        rate_limit::ip_rate_limit,
+        requests::HoneypotField,
+    },
     configs::AppConfigs,
     cors::parse_allowed_origins,
-    services::mailer::Mailer,
+    services::{
+        captcha::{CaptchaVerifier, build_verifier},
+        dead_letters::DeadLetters,
+        domain_policy::DomainPolicy,
+        duplicates::Duplicates,
+        encryption::FieldCipher,
+        form_token::FormTokens,
+        i18n::I18n,
+        idempotency::IdempotencyKeys,
+        mailer::Mailer,
+        notifier::Notifiers,
+        outbox::Outbox,
+        privacy::Privacy,
+        quarantine::Quarantine,
+        rate_limit::{RateLimiter, TrustedProxies},
+        spam::SpamFilter,
+        storage::Storage,
+        submissions::Submissions,
+        templates::Templates,
+        transport::{MailTransport, build_transport},
+    },
+    shutdown::Shutdown,
 };
 
+#[cfg(all(feature = "shuttle", feature = "standalone"))]
+compile_error!("the `shuttle` and `standalone` features are mutually exclusive");
+
+#[cfg(not(any(feature = "shuttle", feature = "standalone")))]
+compile_error!("either the `shuttle` or the `standalone` feature must be enabled");
+
+const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
 static SENTRY_GUARD: OnceLock<ClientInitGuard> = OnceLock::new();
 
 #[derive(Clone, Debug)]
 pub struct AppState {
+    pub captcha: Option<Arc<dyn CaptchaVerifier>>,
     pub configs: AppConfigs,
-    pub mailer: Mailer,
+    pub dead_letters: DeadLetters,
+    pub domain_policy: DomainPolicy,
+    pub duplicates: Option<Duplicates>,
+    pub form_tokens: Option<FormTokens>,
+    pub i18n: I18n,
+    pub idempotency_keys: IdempotencyKeys,
+    pub outbox: Outbox,
+    pub privacy: Privacy,
+    pub quarantine: Quarantine,
+    pub spam: SpamFilter,
+    pub submissions: Submissions,
+    pub trusted_proxies: TrustedProxies,
+    pub ip_rate_limiter: RateLimiter,
+    pub email_rate_limiter: RateLimiter,
+}
+
+impl FromRef<Arc<AppState>> for I18n {
+    fn from_ref(state: &Arc<AppState>) -> Self {
+        state.i18n.clone()
+    }
+}
+
+impl FromRef<Arc<AppState>> for HoneypotField {
+    fn from_ref(state: &Arc<AppState>) -> Self {
+        HoneypotField(state.configs.honeypot_field.clone())
+    }
 }
 
 fn build_cors_layer(allowed_origins: &[String]) -> CorsLayer {
@@ -48,7 +125,14 @@ fn build_cors_layer(allowed_origins: &[String]) -> CorsLayer {
 
     CorsLayer::new()
         .allow_origin(AllowOrigin::predicate(predicate))
-        .allow_headers([header::ACCEPT, header::CONTENT_TYPE, header::AUTHORIZATION])
+        .allow_headers([
+            header::ACCEPT,
+            header::CONTENT_TYPE,
+            header::AUTHORIZATION,
+            IDEMPOTENCY_KEY,
+            X_REQUEST_ID,
+        ])
+        .expose_headers([IDEMPOTENT_REPLAYED, X_REQUEST_ID])
         .allow_methods([Method::GET, Method::HEAD, Method::OPTIONS, Method::POST])
 }
 
@@ -85,24 +169,158 @@ fn tracing_init() {
     tracing_subscriber::registry().with(filter_layer).with(fmt_layer).init();
 }
 
-#[shuttle_runtime::main]
-async fn axum(#[ShuttleSecrets] secrets: ShuttleSecretStore) -> ShuttleAxum {
-    tracing_init();
+async fn build_app(
+    configs: AppConfigs,
+    transport: Arc<dyn MailTransport>,
+    shutdown: &Shutdown,
+) -> anyhow::Result<Router> {
+    let i18n = I18n::load(&configs.locales_dir, &configs.default_locale)
+        .context("couldn't load locales")?;
+    let templates = Templates::load(&configs.templates_dir, &configs.subject_template)
+        .context("couldn't load templates")?;
+    if configs.templates_hot_reload {
+        templates.spawn_watcher(shutdown).context("couldn't watch templates")?;
+    }
+
+    let spam = SpamFilter::new(&configs).context("couldn't create spam filter")?;
+    let mailer = Mailer::new(&configs, transport, templates, i18n.clone(), spam.clone())
+        .context("couldn't create mailer")?;
+    let storage = Storage::open(&configs.database_path).context("couldn't open storage")?;
 
-    let configs = AppConfigs::new(secrets).context("couldn't load app configs")?;
-    let mailer = Mailer::new(&configs).context("couldn't create mailer")?;
+    let cipher = FieldCipher::new(&configs).context("couldn't create field cipher")?;
+    let notifiers = Notifiers::new(&configs, storage.clone(), cipher.clone())
+        .context("couldn't create notifiers")?;
+    let webhooks = notifiers.queue();
+    notifiers.spawn_worker(shutdown);
+    let dead_letters = DeadLetters::new(storage.clone(), cipher.clone(), webhooks.clone());
+    let submissions = Submissions::new(storage.clone(), cipher.clone());
+    let quarantine = Quarantine::new(storage.clone(), cipher.clone(), webhooks.clone());
+    let privacy = Privacy::new(storage.clone(), cipher.clone());
+    if configs.retention_days > 0 {
+        privacy.clone().spawn_retention(&configs, shutdown);
+    }
+    let idempotency_keys = IdempotencyKeys::new(storage.clone(), configs.idempotency_ttl);
+    let duplicates =
+        (configs.duplicate_window > 0).then(|| Duplicates::new(configs.duplicate_window));
+    let outbox = Outbox::new(storage, cipher, webhooks);
+    outbox.clone().spawn_worker(mailer, configs.clone(), shutdown);
+
+    let domain_policy = DomainPolicy::new(&configs).context("couldn't create domain policy")?;
+    let captcha = build_verifier(&configs).context("couldn't create captcha verifier")?;
+    let form_tokens = configs.form_token_secret.as_deref().map(|secret| {
+        FormTokens::new(
+            secret,
+            configs.form_token_min_age,
+            configs.form_token_max_age,
+        )
+    });
+    let trusted_proxies =
+        TrustedProxies::new(&configs.trusted_proxies).context("couldn't parse trusted proxies")?;
+    let ip_rate_limiter =
+        RateLimiter::new(configs.ip_rate_limit_burst, configs.ip_rate_limit_refill);
+    let email_rate_limiter = RateLimiter::new(
+        configs.email_rate_limit_burst,
+        configs.email_rate_limit_refill,
+    );
+    ip_rate_limiter.clone().spawn_pruner(shutdown);
+    email_rate_limiter.clone().spawn_pruner(shutdown);
 
     sentry_init(&configs);
 
-    let concurrency_limit = configs.concurrency_limit;
-    let cors_layer = build_cors_layer(&configs.allow_cors_origins);
+    Ok(build_router(Arc::new(AppState {
+        captcha,
+        configs,
+        dead_letters,
+        domain_policy,
+        duplicates,
+        form_tokens,
+        i18n,
+        idempotency_keys,
+        outbox,
+        privacy,
+        quarantine,
+        spam,
+        submissions,
+        trusted_proxies,
+        ip_rate_limiter,
+        email_rate_limiter,
+    })))
+}
 
-    let app = ShuttleRouter::new()
+fn build_router(state: Arc<AppState>) -> Router {
+    let concurrency_limit = state.configs.concurrency_limit;
+    let cors_layer = build_cors_layer(&state.configs.allow_cors_origins);
+
+    let admin = Router::new()
+        .route("/dead-letters", get(list_dead_letters_handler))
+        .route("/dead-letters/{id}", get(get_dead_letter_handler))
+        .route(
+            "/dead-letters/{id}/replay",
+            post(replay_dead_letter_handler),
+        )
+        .route("/quarantine", get(list_quarantine_handler))
+        .route("/quarantine/{id}", get(get_quarantined_handler))
+        .route(
+            "/quarantine/{id}/release",
+            post(release_quarantined_handler),
+        )
+        .route("/encryption/rotate", post(rotate_keys_handler))
+        .route("/erasures", get(list_erasures_handler).post(erase_handler))
+        .route("/submissions", get(list_submissions_handler))
+        .route("/submissions/export", get(export_submissions_handler))
+        .route("/submissions/{id}", get(get_submission_handler))
+        .route_layer(from_fn_with_state(Arc::clone(&state), admin_auth));
+
+    Router::new()
         .route("/api/v1/alive", get(alive_handler))
-        .route("/api/v1/send-message", post(send_message_handler))
+        .route("/api/v1/form-token", get(form_token_handler))
+        .route(
+            "/api/v1/send-message",
+            post(send_message_handler)
+                .route_layer(from_fn_with_state(Arc::clone(&state), ip_rate_limit))
+                // Outermost, so a replay is answered without costing the client a token
+                .route_layer(from_fn_with_state(Arc::clone(&state), idempotency)),
+        )
+        .nest("/api/v1/admin", admin)
         .layer(cors_layer)
         .layer(ConcurrencyLimitLayer::new(concurrency_limit))
-        .with_state(Arc::new(AppState { configs, mailer }));
+        .layer(PropagateRequestIdLayer::new(X_REQUEST_ID))
+        .layer(SetRequestIdLayer::new(X_REQUEST_ID, MakeRequestUuid))
+        .with_state(state)
+}
+
+#[cfg(feature = "shuttle")]
+#[shuttle_runtime::main]
+async fn axum(
+    #[ShuttleSecrets] secrets: ShuttleSecretStore,
+) -> Result<ShuttleService, shuttle_runtime::Error> {
+    tracing_init();
+
+    let configs = AppConfigs::from_shuttle(secrets).context("couldn't load app configs")?;
+    let timeout = Duration::from_secs(configs.shutdown_timeout);
+    let shutdown = Shutdown::new();
+    let transport = build_transport(&configs).context("couldn't create mail transport")?;
+    let app = build_app(configs, transport, &shutdown).await?;
+
+    Ok(ShuttleService { app, shutdown, timeout })
+}
+
+#[cfg(feature = "standalone")]
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_init();
+
+    let configs = AppConfigs::from_env().context("couldn't load app configs")?;
+    let listen_addr = configs.listen_addr.clone();
+    let timeout = Duration::from_secs(configs.shutdown_timeout);
+    let shutdown = Shutdown::new();
+    let transport = build_transport(&configs).context("couldn't create mail transport")?;
+    let app = build_app(configs, transport, &shutdown).await?;
+
+    let listener = tokio::net::TcpListener::bind(&listen_addr)
+        .await
+        .with_context(|| format!("couldn't bind to {listen_addr}"))?;
+    tracing::info!("listening on {listen_addr}");
 
-    Ok(app.into())
+    shutdown.serve(listener, app, timeout).await
 }
@@ -0,0 +1,183 @@
+use std::{fmt::Debug, net::IpAddr, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::{api::errors::CaptchaErrors, configs::AppConfigs};
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptchaProviderKind {
+    #[default]
+    None,
+    Turnstile,
+    Hcaptcha,
+    Recaptcha,
+}
+
+impl CaptchaProviderKind {
+    /// Every provider speaks the same `siteverify` protocol, only the endpoint differs
+    fn verify_url(self) -> Option<&'static str> {
+        match self {
+            Self::None => None,
+            Self::Turnstile => Some("https://challenges.cloudflare.com/turnstile/v0/siteverify"),
+            Self::Hcaptcha => Some("https://api.hcaptcha.com/siteverify"),
+            Self::Recaptcha => Some("https://www.google.com/recaptcha/api/siteverify"),
+        }
+    }
+}
+
+#[async_trait]
+pub trait CaptchaVerifier: Debug + Send + Sync {
+    /// Tells whether the provider vouches for the token
+    async fn verify(&self, token: &str, remote_ip: IpAddr) -> Result<bool, CaptchaErrors>;
+}
+
+#[derive(Debug, Deserialize)]
+struct SiteVerifyResponse {
+    success: bool,
+    #[serde(default, rename = "error-codes")]
+    error_codes: Vec<String>,
+}
+
+/// Verifies tokens against a `siteverify` endpoint
+#[derive(Clone, Debug)]
+pub struct HttpCaptchaVerifier {
+    client: reqwest::Client,
+    url: String,
+    secret: String,
+}
+
+impl HttpCaptchaVerifier {
+    pub fn new(url: &str, secret: &str, timeout: u64) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(timeout))
+            .build()
+            .context("couldn't create the captcha HTTP client")?;
+
+        Ok(Self { client, url: url.to_string(), secret: secret.to_string() })
+    }
+}
+
+#[async_trait]
+impl CaptchaVerifier for HttpCaptchaVerifier {
+    async fn verify(&self, token: &str, remote_ip: IpAddr) -> Result<bool, CaptchaErrors> {
+        let remote_ip = remote_ip.to_string();
+        let response: SiteVerifyResponse = self
+            .client
+            .post(&self.url)
+            .form(&[
+                ("secret", self.secret.as_str()),
+                ("response", token),
+                ("remoteip", remote_ip.as_str()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        if !response.success {
+            tracing::debug!("captcha rejected: {:?}", response.error_codes);
+        }
+
+        Ok(response.success)
+    }
+}
+
+pub fn build_verifier(configs: &AppConfigs) -> anyhow::Result<Option<Arc<dyn CaptchaVerifier>>> {
+    let Some(default_url) = configs.captcha_provider.verify_url() else {
+        return Ok(None);
+    };
+
+    let url = configs.captcha_verify_url.as_deref().unwrap_or(default_url);
+    let secret = configs.captcha_secret.as_deref().context("<captcha_secret> is missing")?;
+    let verifier = HttpCaptchaVerifier::new(url, secret, configs.captcha_timeout)?;
+
+    tracing::info!("captcha provider: {:?}", configs.captcha_provider);
+
+    Ok(Some(Arc::new(verifier)))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        collections::HashMap,
+        net::Ipv4Addr,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{Form, Json, Router, extract::State, http::StatusCode, routing::post};
+    use serde_json::json;
+
+    use super::*;
+
+    pub(crate) type Calls = Arc<Mutex<Vec<HashMap<String, String>>>>;
+
+    /// Stands in for the provider, answering every call with the status and the verdict
+    pub(crate) async fn spawn_siteverify(status: StatusCode, success: bool) -> (String, Calls) {
+        let calls = Calls::default();
+        let app =
+            Router::new()
+                .route(
+                    "/siteverify",
+                    post(
+                        move |State(calls): State<Calls>,
+                              Form(form): Form<HashMap<String, String>>| async move {
+                            calls.lock().unwrap().push(form);
+                            (
+                                status,
+                                Json(json!({ "success": success, "error-codes": [] })),
+                            )
+                        },
+                    ),
+                )
+                .with_state(calls.clone());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/siteverify", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        (url, calls)
+    }
+
+    async fn verify(status: StatusCode, success: bool) -> (Result<bool, CaptchaErrors>, Calls) {
+        let (url, calls) = spawn_siteverify(status, success).await;
+        let verifier = HttpCaptchaVerifier::new(&url, "captcha_secret", 1000).unwrap();
+
+        (
+            verifier.verify("token", IpAddr::V4(Ipv4Addr::LOCALHOST)).await,
+            calls,
+        )
+    }
+
+    #[tokio::test]
+    async fn vouches_for_an_accepted_token() {
+        let (verified, calls) = verify(StatusCode::OK, true).await;
+
+        assert!(verified.unwrap());
+        let calls = calls.lock().unwrap();
+        let [call] = calls.as_slice() else {
+            panic!("expected one call, got {calls:?}");
+        };
+        let field = |name: &str| call.get(name).map(String::as_str);
+        assert_eq!(field("secret"), Some("captcha_secret"));
+        assert_eq!(field("response"), Some("token"));
+        assert_eq!(field("remoteip"), Some("127.0.0.1"));
+    }
+
+    #[tokio::test]
+    async fn refuses_a_rejected_token() {
+        let (verified, _) = verify(StatusCode::OK, false).await;
+
+        assert!(!verified.unwrap());
+    }
+
+    #[tokio::test]
+    async fn fails_on_a_provider_error() {
+        let (verified, _) = verify(StatusCode::BAD_GATEWAY, false).await;
+
+        assert!(verified.is_err());
+    }
+}
@@ -0,0 +1,164 @@
+use rusqlite::{OptionalExtension, Row, params};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use super::{
+    encryption::{FieldCipher, SealedForm},
+    notifier::{SUBMISSION_REPLAYED, WebhookQueue},
+    outbox,
+    storage::{Storage, datetime, unix_now},
+    submissions::{self, SubmissionStatus},
+};
+use crate::api::errors::StorageErrors;
+
+const DEAD_LETTER_COLUMNS: &str = "id, outbox_id, COALESCE(ticket, outbox_id), payload, \
+                                   last_error, attempts, created_at, failed_at, replayed_at, \
+                                   replay_count";
+
+/// Submission the outbox worker gave up on, kept for inspection and replay
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetter {
+    pub id: i64,
+    pub outbox_id: i64,
+    pub ticket: i64,
+    pub payload: serde_json::Value,
+    pub last_error: Option<String>,
+    pub attempts: u32,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub failed_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub replayed_at: Option<OffsetDateTime>,
+    pub replay_count: u32,
+}
+
+impl DeadLetter {
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        let payload: String = row.get(3)?;
+
+        Ok(Self {
+            id: row.get(0)?,
+            outbox_id: row.get(1)?,
+            ticket: row.get(2)?,
+            payload: serde_json::from_str(&payload).unwrap_or(serde_json::Value::String(payload)),
+            last_error: row.get(4)?,
+            attempts: row.get(5)?,
+            created_at: datetime(row.get(6)?),
+            failed_at: datetime(row.get(7)?),
+            replayed_at: row.get::<_, Option<i64>>(8)?.map(datetime),
+            replay_count: row.get(9)?,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DeadLetters {
+    storage: Storage,
+    cipher: FieldCipher,
+    webhooks: WebhookQueue,
+}
+
+impl DeadLetters {
+    pub fn new(storage: Storage, cipher: FieldCipher, webhooks: WebhookQueue) -> Self {
+        Self { storage, cipher, webhooks }
+    }
+
+    pub async fn list(&self, limit: u32, offset: u32) -> Result<Vec<DeadLetter>, StorageErrors> {
+        let mut dead_letters = self
+            .storage
+            .call(move |conn| {
+                let mut stmt = conn.prepare_cached(&format!(
+                    "SELECT {DEAD_LETTER_COLUMNS} FROM dead_letters
+                     ORDER BY id DESC LIMIT ?1 OFFSET ?2"
+                ))?;
+                let dead_letters = stmt
+                    .query_map(params![limit, offset], DeadLetter::from_row)?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(dead_letters)
+            })
+            .await?;
+        self.decrypt(&mut dead_letters);
+
+        Ok(dead_letters)
+    }
+
+    pub async fn get(&self, id: i64) -> Result<Option<DeadLetter>, StorageErrors> {
+        let mut dead_letter = self
+            .storage
+            .call(move |conn| {
+                let dead_letter = conn
+                    .query_row(
+                        &format!("SELECT {DEAD_LETTER_COLUMNS} FROM dead_letters WHERE id = ?1"),
+                        params![id],
+                        DeadLetter::from_row,
+                    )
+                    .optional()?;
+                Ok(dead_letter)
+            })
+            .await?;
+        self.decrypt(dead_letter.iter_mut());
+
+        Ok(dead_letter)
+    }
+
+    /// Puts the dead letter back into the outbox and returns the new outbox job id
+    pub async fn replay(&self, id: i64) -> Result<Option<i64>, StorageErrors> {
+        let webhooks = self.webhooks.clone();
+
+        self.storage
+            .call(move |conn| {
+                let tx = conn.transaction()?;
+
+                let dead_letter = tx
+                    .query_row(
+                        "SELECT payload, email_index, COALESCE(ticket, outbox_id) FROM dead_letters
+                         WHERE id = ?1",
+                        params![id],
+                        |row| {
+                            let form =
+                                SealedForm { payload: row.get(0)?, email_index: row.get(1)? };
+                            Ok((form, row.get::<_, i64>(2)?))
+                        },
+                    )
+                    .optional()?;
+                let Some((form, ticket)) = dead_letter else {
+                    return Ok(None);
+                };
+
+                let outbox_id = outbox::insert(&tx, &form, Some(ticket))?;
+                submissions::set_status(&tx, ticket, SubmissionStatus::Received, None)?;
+
+                let submission_id = tx
+                    .query_row(
+                        "SELECT id FROM submissions WHERE ticket = ?1",
+                        params![ticket],
+                        |row| row.get::<_, i64>(0),
+                    )
+                    .optional()?;
+                if let Some(submission_id) = submission_id {
+                    webhooks.insert(&tx, submission_id, SUBMISSION_REPLAYED)?;
+                }
+
+                tx.execute(
+                    "UPDATE dead_letters SET replayed_at = ?2, replay_count = replay_count + 1
+                     WHERE id = ?1",
+                    params![id, unix_now()],
+                )?;
+                tx.commit()?;
+
+                Ok(Some(outbox_id))
+            })
+            .await
+    }
+
+    fn decrypt<'a>(&self, dead_letters: impl IntoIterator<Item = &'a mut DeadLetter>) {
+        for dead_letter in dead_letters {
+            self.cipher.open_payload(
+                &mut dead_letter.payload,
+                format_args!("dead letter #{}", dead_letter.id),
+            );
+        }
+    }
+}
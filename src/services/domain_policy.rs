@@ -0,0 +1,176 @@
+use std::{borrow::Cow, collections::HashSet, sync::Arc};
+
+use globset::{GlobBuilder, GlobMatcher};
+use validator::ValidationError;
+
+use crate::configs::AppConfigs;
+
+/// Bundled at build time, so the list can't go missing on a deployment
+const DISPOSABLE_DOMAINS: &str = include_str!("../../assets/disposable-domains.txt");
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DomainVerdict {
+    Allowed,
+    Disposable,
+    Blocked,
+}
+
+impl DomainVerdict {
+    /// Validation code of a rejected domain, translated by the `validation.<code>` catalog key
+    pub fn code(self) -> Option<&'static str> {
+        match self {
+            Self::Allowed => None,
+            Self::Disposable => Some("email_domain_disposable"),
+            Self::Blocked => Some("email_domain_blocked"),
+        }
+    }
+}
+
+/// Domains of the disposable providers, their subdomains included
+#[derive(Clone, Debug)]
+pub struct DisposableDomains(Arc<HashSet<&'static str>>);
+
+impl DisposableDomains {
+    pub fn bundled() -> Self {
+        let domains = DISPOSABLE_DOMAINS
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+
+        Self(Arc::new(domains))
+    }
+
+    pub fn contains(&self, domain: &str) -> bool {
+        let mut candidate = domain;
+        loop {
+            if self.0.contains(candidate) {
+                return true;
+            }
+            match candidate.split_once('.') {
+                Some((_, parent)) if parent.contains('.') => candidate = parent,
+                _ => return false,
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum DomainPattern {
+    Exact(String),
+    Wildcard { matcher: GlobMatcher, base_domain: String },
+}
+
+impl DomainPattern {
+    fn matches(&self, domain: &str) -> bool {
+        match self {
+            DomainPattern::Exact(exact) => domain == exact,
+            DomainPattern::Wildcard { matcher, base_domain } => {
+                matcher.is_match(domain) && domain != base_domain
+            }
+        }
+    }
+}
+
+/// Decides whether an email domain is welcome, the allow list overriding everything else
+#[derive(Clone, Debug)]
+pub struct DomainPolicy {
+    disposable: Option<DisposableDomains>,
+    allow: Arc<Vec<DomainPattern>>,
+    deny: Arc<Vec<DomainPattern>>,
+}
+
+impl DomainPolicy {
+    pub fn new(configs: &AppConfigs) -> anyhow::Result<Self> {
+        Self::build(configs, configs.block_disposable_domains)
+    }
+
+    /// Flags the disposable providers even when they aren't blocked, for the spam score
+    pub fn scoring(configs: &AppConfigs) -> anyhow::Result<Self> {
+        Self::build(configs, true)
+    }
+
+    fn build(configs: &AppConfigs, disposable: bool) -> anyhow::Result<Self> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| {
+                    compile_domain_pattern(pattern)
+                        .map_err(|err| anyhow::anyhow!("invalid domain pattern <{pattern}>: {err}"))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()
+        };
+
+        Ok(Self {
+            disposable: disposable.then(DisposableDomains::bundled),
+            allow: Arc::new(compile(&configs.email_domain_allowlist)?),
+            deny: Arc::new(compile(&configs.email_domain_denylist)?),
+        })
+    }
+
+    pub fn check(&self, email: &str) -> DomainVerdict {
+        let Some(domain) = email_domain(email) else {
+            return DomainVerdict::Allowed;
+        };
+
+        if self.allow.iter().any(|pattern| pattern.matches(&domain)) {
+            return DomainVerdict::Allowed;
+        }
+        if self.deny.iter().any(|pattern| pattern.matches(&domain)) {
+            return DomainVerdict::Blocked;
+        }
+        if self.disposable.as_ref().is_some_and(|disposable| disposable.contains(&domain)) {
+            return DomainVerdict::Disposable;
+        }
+
+        DomainVerdict::Allowed
+    }
+}
+
+pub fn email_domain(email: &str) -> Option<String> {
+    let (_, domain) = email.trim().rsplit_once('@')?;
+    Some(domain.trim_end_matches('.').to_ascii_lowercase())
+}
+
+pub fn validate_domain_pattern(pattern: &str) -> Result<(), ValidationError> {
+    compile_domain_pattern(pattern).map(|_| ())
+}
+
+/// Takes either `example.com` or `*.example.com`, the CORS origins way
+fn compile_domain_pattern(pattern: &str) -> Result<DomainPattern, ValidationError> {
+    let pattern = pattern.trim().to_ascii_lowercase();
+
+    let Some(base_domain) = pattern.strip_prefix("*.") else {
+        if pattern.is_empty() || pattern.contains(['*', '@', '/', ':']) || !pattern.contains('.') {
+            return Err(invalid_domain_error("must be a domain like example.com"));
+        }
+        return Ok(DomainPattern::Exact(pattern));
+    };
+
+    if base_domain.is_empty() || !base_domain.contains('.') {
+        return Err(invalid_domain_error(
+            "wildcard base must be a valid domain like example.com",
+        ));
+    }
+
+    if base_domain.contains(['*', '@', '/', ':']) {
+        return Err(invalid_domain_error(
+            "only a single leading wildcard is supported",
+        ));
+    }
+
+    let matcher = GlobBuilder::new(&pattern)
+        .case_insensitive(true)
+        .literal_separator(true)
+        .build()
+        .map_err(|_| invalid_domain_error("invalid wildcard glob pattern"))?
+        .compile_matcher();
+
+    Ok(DomainPattern::Wildcard { matcher, base_domain: base_domain.to_string() })
+}
+
+fn invalid_domain_error(message: &str) -> ValidationError {
+    let mut err = ValidationError::new("invalid_domain_pattern");
+    err.message = Some(Cow::Owned(message.to_string()));
+    err
+}
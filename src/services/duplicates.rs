@@ -0,0 +1,71 @@
+use rusqlite::{Connection, params};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use super::storage::unix_now;
+use crate::api::models::LetsStartForm;
+
+/// What happens to a message, which was already sent within the window
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateAction {
+    /// Answers as if it was sent, but doesn't send it again
+    #[default]
+    Accept,
+    /// Answers with a conflict error
+    Reject,
+}
+
+/// Remembers the fingerprints of the sent messages for a while
+#[derive(Clone, Copy, Debug)]
+pub struct Duplicates {
+    window: i64,
+}
+
+impl Duplicates {
+    pub fn new(window: u64) -> Self {
+        Self { window: window as i64 }
+    }
+
+    /// Fingerprints the message, to be recorded along with the submission it belongs to
+    pub fn claim(&self, form: &LetsStartForm) -> FingerprintClaim {
+        FingerprintClaim { fingerprint: fingerprint(form), window: self.window }
+    }
+}
+
+#[derive(Debug)]
+pub struct FingerprintClaim {
+    fingerprint: String,
+    window: i64,
+}
+
+impl FingerprintClaim {
+    /// Records the fingerprint within the caller's transaction, tells whether it was already there
+    pub(super) fn record(&self, conn: &Connection) -> rusqlite::Result<bool> {
+        let now = unix_now();
+        conn.execute(
+            "DELETE FROM submission_fingerprints WHERE expires_at <= ?1",
+            params![now],
+        )?;
+
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO submission_fingerprints (fingerprint, created_at, expires_at)
+             VALUES (?1, ?2, ?3)",
+            params![self.fingerprint, now, now + self.window],
+        )?;
+        Ok(inserted == 0)
+    }
+}
+
+/// Hashes the address and the description, ignoring the case and the whitespace
+pub(super) fn fingerprint(form: &LetsStartForm) -> String {
+    let email = form.email.trim().to_lowercase();
+    let description =
+        form.project_description.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+
+    let mut hasher = Sha256::new();
+    hasher.update(email.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(description.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
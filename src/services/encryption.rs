@@ -0,0 +1,365 @@
+use std::{collections::HashMap, sync::Arc};
+
+use aes_gcm::{
+    Aes256Gcm, Key, KeyInit, Nonce,
+    aead::{Aead, AeadCore, OsRng, Payload},
+};
+use anyhow::{Context, bail};
+use base64::{
+    Engine,
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+};
+use serde_json::Value;
+
+use super::signing::hmac_sha256;
+use crate::{
+    api::{
+        errors::{CryptoErrors, StorageErrors},
+        models::LetsStartForm,
+    },
+    configs::AppConfigs,
+};
+
+/// Payload fields holding personal data, the rest stays searchable
+pub const ENCRYPTED_FIELDS: &[&str] = &["email", "name", "projectDescription"];
+
+/// Marks an encrypted value, formatted as `enc:v1:<key id>:<base64 nonce and ciphertext>`
+const PREFIX: &str = "enc:v1:";
+const NONCE_LEN: usize = 12;
+
+/// Parses a `<key id>:<base64 key>` entry of `encryption_keys`
+pub fn parse_key(entry: &str) -> anyhow::Result<(String, Aes256Gcm)> {
+    let (id, key) = entry.trim().split_once(':').context("must look like <key id>:<base64 key>")?;
+    if id.is_empty() || !id.chars().all(|char| char.is_ascii_alphanumeric() || char == '-') {
+        bail!("the key id must be alphanumeric");
+    }
+
+    let key = STANDARD.decode(key).context("the key must be base64")?;
+    if key.len() != 32 {
+        bail!("the key must be 32 bytes long");
+    }
+
+    Ok((
+        id.to_string(),
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+    ))
+}
+
+/// Form serialized with its personal fields sealed, along with the blind index of its address
+#[derive(Clone, Debug)]
+pub struct SealedForm {
+    pub payload: String,
+    pub email_index: Option<String>,
+}
+
+/// Authenticated encryption of the personal payload fields, with the key id kept next to
+/// every value, so the older keys keep decrypting after a rotation
+#[derive(Clone)]
+pub struct FieldCipher {
+    keys: Arc<HashMap<String, Aes256Gcm>>,
+    active: Option<String>,
+    index_secret: Option<Vec<u8>>,
+}
+
+impl std::fmt::Debug for FieldCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FieldCipher").field("active", &self.active).finish_non_exhaustive()
+    }
+}
+
+impl FieldCipher {
+    pub fn new(configs: &AppConfigs) -> anyhow::Result<Self> {
+        let keys = configs
+            .encryption_keys
+            .iter()
+            .map(|entry| parse_key(entry))
+            .collect::<anyhow::Result<Vec<_>>>()
+            .context("invalid encryption key")?;
+
+        // The first key encrypts, unless another one is picked explicitly
+        let active =
+            configs.encryption_key_id.clone().or_else(|| keys.first().map(|(id, _)| id.clone()));
+        if let Some(active) = &active {
+            tracing::info!("encrypting the submissions with the key <{active}>");
+        }
+
+        Ok(Self {
+            keys: Arc::new(keys.into_iter().collect()),
+            active,
+            index_secret: configs
+                .email_index_secret
+                .as_ref()
+                .map(|secret| secret.as_bytes().to_vec()),
+        })
+    }
+
+    /// Encrypts the plaintext personal fields with the active key, if there is one
+    pub fn encrypt_payload(&self, payload: &mut Value) -> Result<(), CryptoErrors> {
+        let Some(active) = &self.active else {
+            return Ok(());
+        };
+        let cipher = self.cipher(active)?;
+
+        for field in ENCRYPTED_FIELDS {
+            let Some(Value::String(value)) = payload.get_mut(*field) else {
+                continue;
+            };
+            if value.is_empty() || value.starts_with(PREFIX) {
+                continue;
+            }
+
+            let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+            let ciphertext = cipher
+                .encrypt(
+                    &nonce,
+                    Payload { msg: value.as_bytes(), aad: field.as_bytes() },
+                )
+                .map_err(|_| CryptoErrors::Encryption)?;
+
+            let mut sealed = nonce.to_vec();
+            sealed.extend(ciphertext);
+            *value = format!("{PREFIX}{active}:{}", URL_SAFE_NO_PAD.encode(sealed));
+        }
+
+        Ok(())
+    }
+
+    /// Decrypts the encrypted fields, whichever of the configured keys they were sealed with
+    pub fn decrypt_payload(&self, payload: &mut Value) -> Result<(), CryptoErrors> {
+        for field in ENCRYPTED_FIELDS {
+            let Some(Value::String(value)) = payload.get_mut(*field) else {
+                continue;
+            };
+            let Some(sealed) = value.strip_prefix(PREFIX) else {
+                continue;
+            };
+
+            let (key_id, sealed) = sealed.split_once(':').ok_or(CryptoErrors::Malformed)?;
+            let sealed = URL_SAFE_NO_PAD.decode(sealed).map_err(|_| CryptoErrors::Malformed)?;
+            if sealed.len() < NONCE_LEN {
+                return Err(CryptoErrors::Malformed);
+            }
+            let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+
+            let plaintext = self
+                .cipher(key_id)?
+                .decrypt(
+                    Nonce::from_slice(nonce),
+                    Payload { msg: ciphertext, aad: field.as_bytes() },
+                )
+                .map_err(|_| CryptoErrors::Decryption)?;
+            *value = String::from_utf8(plaintext).map_err(|_| CryptoErrors::Malformed)?;
+        }
+
+        Ok(())
+    }
+
+    /// Prepares the form for any of the tables holding a payload
+    pub fn seal_form(&self, form: &LetsStartForm) -> Result<SealedForm, StorageErrors> {
+        let mut payload = serde_json::to_value(form)?;
+        self.encrypt_payload(&mut payload)?;
+
+        Ok(SealedForm { payload: payload.to_string(), email_index: self.email_index(&form.email) })
+    }
+
+    pub fn open_form(&self, payload: &str) -> Result<LetsStartForm, StorageErrors> {
+        let mut payload = serde_json::from_str(payload)?;
+        self.decrypt_payload(&mut payload)?;
+
+        Ok(serde_json::from_value(payload)?)
+    }
+
+    /// Decrypts a payload for the admin reads, leaving it sealed when it can't be opened, e.g.
+    /// after its key was dropped
+    pub fn open_payload(&self, payload: &mut Value, owner: std::fmt::Arguments<'_>) {
+        if let Err(err) = self.decrypt_payload(payload) {
+            tracing::warn!("{owner} couldn't be decrypted: {:?}", err);
+        }
+    }
+
+    /// Tells whether a field is plaintext or sealed with another key than the active one
+    pub fn needs_rotation(&self, payload: &Value) -> bool {
+        let Some(active) = &self.active else {
+            return false;
+        };
+        let active_prefix = format!("{PREFIX}{active}:");
+
+        ENCRYPTED_FIELDS.iter().any(|field| match payload.get(*field) {
+            Some(Value::String(value)) => !value.is_empty() && !value.starts_with(&active_prefix),
+            _ => false,
+        })
+    }
+
+    /// Keyed hash of the address, so it can be looked up without being decrypted
+    pub fn email_index(&self, email: &str) -> Option<String> {
+        let secret = self.index_secret.as_ref()?;
+        let index = hmac_sha256(secret, email.trim().to_lowercase().as_bytes());
+
+        Some(URL_SAFE_NO_PAD.encode(index))
+    }
+
+    fn cipher(&self, key_id: &str) -> Result<&Aes256Gcm, CryptoErrors> {
+        self.keys.get(key_id).ok_or_else(|| CryptoErrors::UnknownKey(key_id.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn key(id: &str, byte: u8) -> String {
+        format!("{id}:{}", STANDARD.encode([byte; 32]))
+    }
+
+    fn cipher(keys: &[String], active: Option<&str>) -> FieldCipher {
+        let mut configs = AppConfigs::for_tests(&[]);
+        configs.encryption_keys = keys.to_vec();
+        configs.encryption_key_id = active.map(str::to_string);
+        configs.email_index_secret = Some("an email index secret of 32 chars".to_string());
+
+        FieldCipher::new(&configs).unwrap()
+    }
+
+    fn payload() -> Value {
+        json!({
+            "email": "jane@example.com",
+            "name": "Jane",
+            "projectDescription": "A marketing site for a small bakery, with an online order form.",
+            "minBudget": 1000,
+            "maxBudget": 2000,
+        })
+    }
+
+    fn sealed(cipher: &FieldCipher) -> Value {
+        let mut payload = payload();
+        cipher.encrypt_payload(&mut payload).unwrap();
+        payload
+    }
+
+    #[test]
+    fn round_trips_the_personal_fields() {
+        let cipher = cipher(&[key("key-1", 1)], None);
+
+        let payload = sealed(&cipher);
+        for field in ENCRYPTED_FIELDS {
+            assert!(payload[*field].as_str().unwrap().starts_with("enc:v1:key-1:"));
+        }
+        assert_eq!(payload["minBudget"], 1000);
+
+        // Sealed values are left as they are on a second pass
+        let mut twice = payload.clone();
+        cipher.encrypt_payload(&mut twice).unwrap();
+        assert_eq!(twice, payload);
+
+        let form: LetsStartForm = serde_json::from_value(self::payload()).unwrap();
+        let sealed_form = cipher.seal_form(&form).unwrap();
+        assert!(!sealed_form.payload.contains("jane@example.com"));
+        let opened = cipher.open_form(&sealed_form.payload).unwrap();
+        assert_eq!(opened.email, "jane@example.com");
+        assert_eq!(opened.name, "Jane");
+        assert_eq!(opened.project_description, form.project_description);
+    }
+
+    #[test]
+    fn rejects_swapped_and_tampered_fields() {
+        let cipher = cipher(&[key("key-1", 1)], None);
+        let payload = sealed(&cipher);
+
+        // The field name is the associated data, so a value can't be moved to another field
+        let mut swapped = payload.clone();
+        swapped["name"] = payload["email"].clone();
+        assert!(matches!(
+            cipher.decrypt_payload(&mut swapped),
+            Err(CryptoErrors::Decryption)
+        ));
+
+        let mut tampered = payload.clone();
+        let value = tampered["email"].as_str().unwrap().to_string();
+        let (key_id, sealed) = value.strip_prefix(PREFIX).unwrap().split_once(':').unwrap();
+        let mut sealed = URL_SAFE_NO_PAD.decode(sealed).unwrap();
+        sealed[NONCE_LEN] ^= 1;
+        tampered["email"] = Value::String(format!(
+            "{PREFIX}{key_id}:{}",
+            URL_SAFE_NO_PAD.encode(sealed)
+        ));
+        assert!(matches!(
+            cipher.decrypt_payload(&mut tampered),
+            Err(CryptoErrors::Decryption)
+        ));
+
+        let mut malformed = payload;
+        malformed["email"] = Value::String("enc:v1:key-1".to_string());
+        assert!(matches!(
+            cipher.decrypt_payload(&mut malformed),
+            Err(CryptoErrors::Malformed)
+        ));
+    }
+
+    #[test]
+    fn decrypts_with_the_older_keys_only_while_they_are_configured() {
+        let old = sealed(&cipher(&[key("key-1", 1)], None));
+
+        let rotated = cipher(&[key("key-2", 2), key("key-1", 1)], None);
+        let mut payload = old.clone();
+        rotated.decrypt_payload(&mut payload).unwrap();
+        assert_eq!(payload, self::payload());
+
+        let dropped = cipher(&[key("key-2", 2)], None);
+        let mut payload = old;
+        let err = dropped.decrypt_payload(&mut payload).unwrap_err();
+        assert!(matches!(err, CryptoErrors::UnknownKey(id) if id == "key-1"));
+    }
+
+    #[test]
+    fn tells_which_payloads_need_a_rotation() {
+        let old = sealed(&cipher(&[key("key-1", 1)], None));
+        let rotated = cipher(&[key("key-1", 1), key("key-2", 2)], Some("key-2"));
+
+        assert!(rotated.needs_rotation(&payload()));
+        assert!(rotated.needs_rotation(&old));
+        assert!(!rotated.needs_rotation(&sealed(&rotated)));
+
+        let mut empty = payload();
+        empty["email"] = Value::String(String::new());
+        empty["name"] = Value::Null;
+        empty["projectDescription"] = sealed(&rotated)["projectDescription"].clone();
+        assert!(!rotated.needs_rotation(&empty));
+
+        let plain = cipher(&[], None);
+        assert!(!plain.needs_rotation(&payload()));
+    }
+
+    #[test]
+    fn indexes_the_normalized_address() {
+        let cipher = cipher(&[key("key-1", 1)], None);
+
+        let index = cipher.email_index("jane@example.com").unwrap();
+        assert_eq!(cipher.email_index(" Jane@Example.COM ").unwrap(), index);
+        assert_ne!(cipher.email_index("john@example.com").unwrap(), index);
+
+        // The index doesn't depend on the encryption keys, which rotate
+        let rotated = self::cipher(&[key("key-2", 2)], None);
+        assert_eq!(rotated.email_index("jane@example.com").unwrap(), index);
+
+        let mut configs = AppConfigs::for_tests(&[]);
+        configs.email_index_secret = None;
+        assert!(FieldCipher::new(&configs).unwrap().email_index("jane@example.com").is_none());
+    }
+
+    #[test]
+    fn leaves_the_unreadable_payloads_sealed() {
+        let old = sealed(&cipher(&[key("key-1", 1)], None));
+        let dropped = cipher(&[key("key-2", 2)], None);
+
+        let mut payload = old.clone();
+        dropped.open_payload(&mut payload, format_args!("submission 1"));
+        assert_eq!(payload, old);
+
+        let rotated = cipher(&[key("key-2", 2), key("key-1", 1)], None);
+        let mut payload = old;
+        rotated.open_payload(&mut payload, format_args!("submission 1"));
+        assert_eq!(payload, self::payload());
+    }
+}
@@ -0,0 +1,192 @@
+use axum::body::Bytes;
+use futures_util::{Stream, stream};
+use serde::Deserialize;
+use time::format_description::well_known::Rfc3339;
+
+use super::submissions::{Submission, SubmissionFilter, Submissions};
+use crate::api::errors::StorageErrors;
+
+/// Rows fetched per round trip, so neither the memory nor the storage lock grow with the export
+const BATCH_SIZE: u32 = 500;
+
+const CSV_HEADER: &[&str] = &[
+    "id",
+    "ticket",
+    "status",
+    "created_at",
+    "sent_at",
+    "name",
+    "email",
+    "min_budget",
+    "max_budget",
+    "project_description",
+    "locale",
+    "request_id",
+    "client_ip",
+    "origin",
+];
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Ndjson,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Ndjson => "ndjson",
+        }
+    }
+
+    fn encode(self, submissions: &[Submission], with_header: bool) -> Result<Bytes, StorageErrors> {
+        match self {
+            Self::Csv => encode_csv(submissions, with_header),
+            Self::Ndjson => encode_ndjson(submissions),
+        }
+    }
+}
+
+/// Streams the matching submissions, oldest first, one batch per chunk
+pub fn export(
+    submissions: Submissions,
+    filter: SubmissionFilter,
+    format: ExportFormat,
+) -> impl Stream<Item = Result<Bytes, StorageErrors>> {
+    // The header goes out even when nothing matches
+    let state = Some((submissions, filter, 0, true));
+
+    stream::try_unfold(state, move |state| async move {
+        let Some((submissions, filter, after_id, is_first)) = state else {
+            return Ok(None);
+        };
+
+        let batch = submissions.batch_after(&filter, after_id, BATCH_SIZE).await?;
+        let chunk = format.encode(&batch, is_first)?;
+
+        let next = match batch.last() {
+            Some(last) if batch.len() == BATCH_SIZE as usize => {
+                Some((submissions, filter, last.id, false))
+            }
+            _ => None,
+        };
+
+        Ok(Some((chunk, next)))
+    })
+}
+
+fn encode_csv(submissions: &[Submission], with_header: bool) -> Result<Bytes, StorageErrors> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    if with_header {
+        writer.write_record(CSV_HEADER)?;
+    }
+
+    for submission in submissions {
+        let field = |key: &str| match &submission.payload[key] {
+            serde_json::Value::Null => String::new(),
+            serde_json::Value::String(value) => escape_formula(value),
+            value => value.to_string(),
+        };
+        let text =
+            |value: &Option<String>| value.as_deref().map(escape_formula).unwrap_or_default();
+        let timestamp = |at: time::OffsetDateTime| at.format(&Rfc3339).unwrap_or_default();
+
+        writer.write_record([
+            submission.id.to_string(),
+            submission.ticket.map(|ticket| ticket.to_string()).unwrap_or_default(),
+            submission.status.as_str().to_string(),
+            timestamp(submission.created_at),
+            submission.sent_at.map(timestamp).unwrap_or_default(),
+            field("name"),
+            field("email"),
+            field("minBudget"),
+            field("maxBudget"),
+            field("projectDescription"),
+            field("locale"),
+            text(&submission.request_id),
+            text(&submission.client_ip),
+            text(&submission.origin),
+        ])?;
+    }
+
+    let buffer = writer.into_inner().map_err(|err| csv::Error::from(err.into_error()))?;
+    Ok(Bytes::from(buffer))
+}
+
+/// Keeps the spreadsheets from running user input as formulas, every free-text column goes
+/// through it, as the headers the client sent are no more trustworthy than the form
+fn escape_formula(value: &str) -> String {
+    if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{value}")
+    } else {
+        value.to_string()
+    }
+}
+
+fn encode_ndjson(submissions: &[Submission]) -> Result<Bytes, StorageErrors> {
+    let mut buffer = Vec::new();
+    for submission in submissions {
+        serde_json::to_writer(&mut buffer, submission)?;
+        buffer.push(b'\n');
+    }
+
+    Ok(Bytes::from(buffer))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use time::OffsetDateTime;
+
+    use super::*;
+    use crate::services::submissions::SubmissionStatus;
+
+    #[test]
+    fn escapes_every_free_text_column() {
+        let submission = Submission {
+            id: 1,
+            ticket: Some(1),
+            payload: json!({
+                "email": "jane@example.com",
+                "minBudget": 1000,
+                "maxBudget": 5000,
+                "name": "=cmd|' /C calc'!A0",
+                "projectDescription": "+1 for the formulas",
+                "locale": "@en",
+            }),
+            status: SubmissionStatus::Sent,
+            request_id: Some("-request".to_string()),
+            client_ip: Some("\t127.0.0.1".to_string()),
+            origin: Some("=HYPERLINK(\"https://evil.example\")".to_string()),
+            last_error: None,
+            created_at: OffsetDateTime::UNIX_EPOCH,
+            updated_at: OffsetDateTime::UNIX_EPOCH,
+            sent_at: None,
+        };
+
+        let csv = encode_csv(&[submission], false).unwrap();
+        let mut reader = csv::ReaderBuilder::new().has_headers(false).from_reader(&csv[..]);
+        let record = reader.records().next().unwrap().unwrap();
+        let column = |name: &str| &record[CSV_HEADER.iter().position(|key| *key == name).unwrap()];
+
+        for name in ["name", "project_description", "locale", "request_id", "client_ip", "origin"] {
+            assert!(
+                column(name).starts_with('\''),
+                "{name} isn't escaped: {}",
+                column(name)
+            );
+        }
+        assert_eq!(column("email"), "jane@example.com");
+        assert_eq!(column("min_budget"), "1000");
+    }
+}
@@ -0,0 +1,79 @@
+use super::{
+    signing::{hmac_sha256, verify_hmac_sha256},
+    storage::unix_now,
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FormTokenError {
+    Invalid,
+    TooFast,
+    Expired,
+}
+
+impl FormTokenError {
+    /// Validation code, translated by the `validation.<code>` catalog key
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::Invalid => "form_token_invalid",
+            Self::TooFast => "form_token_too_fast",
+            Self::Expired => "form_token_expired",
+        }
+    }
+}
+
+/// Signed "form rendered at" tokens, which tell humans from bots by the time they take
+#[derive(Clone)]
+pub struct FormTokens {
+    secret: Vec<u8>,
+    min_age: i64,
+    max_age: i64,
+}
+
+impl std::fmt::Debug for FormTokens {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FormTokens")
+            .field("min_age", &self.min_age)
+            .field("max_age", &self.max_age)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FormTokens {
+    pub fn new(secret: &str, min_age: u64, max_age: u64) -> Self {
+        Self {
+            secret: secret.as_bytes().to_vec(),
+            min_age: min_age as i64,
+            max_age: max_age as i64,
+        }
+    }
+
+    /// Issues a token stamped with the current time, formatted as `<unix time>.<signature>`
+    pub fn issue(&self) -> String {
+        let rendered_at = unix_now().to_string();
+        format!(
+            "{rendered_at}.{}",
+            URL_SAFE_NO_PAD.encode(hmac_sha256(&self.secret, rendered_at.as_bytes()))
+        )
+    }
+
+    pub fn check(&self, token: &str) -> Result<(), FormTokenError> {
+        let (rendered_at, signature) =
+            token.trim().split_once('.').ok_or(FormTokenError::Invalid)?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| FormTokenError::Invalid)?;
+
+        if !verify_hmac_sha256(&self.secret, rendered_at.as_bytes(), &signature) {
+            return Err(FormTokenError::Invalid);
+        }
+
+        let age = unix_now() - rendered_at.parse::<i64>().map_err(|_| FormTokenError::Invalid)?;
+        if age < self.min_age {
+            return Err(FormTokenError::TooFast);
+        }
+        if age > self.max_age {
+            return Err(FormTokenError::Expired);
+        }
+
+        Ok(())
+    }
+}
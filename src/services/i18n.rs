@@ -0,0 +1,129 @@
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+use anyhow::{Context, bail};
+use config::{Config, File, FileFormat};
+
+type Catalog = HashMap<String, String>;
+
+/// Translation catalogs, one `<locale>.toml` file per locale
+#[derive(Clone, Debug)]
+pub struct I18n {
+    inner: Arc<I18nInner>,
+}
+
+#[derive(Debug)]
+struct I18nInner {
+    default_locale: String,
+    catalogs: HashMap<String, Catalog>,
+}
+
+impl I18n {
+    pub fn load(dir: &str, default_locale: &str) -> anyhow::Result<Self> {
+        let mut catalogs = HashMap::new();
+
+        let entries = std::fs::read_dir(dir)
+            .inspect_err(|err| tracing::error!("locales dir error: {:?}", err))
+            .context("couldn't read the locales directory")?;
+        for entry in entries {
+            let path = entry.context("couldn't read the locales directory")?.path();
+            if path.extension().is_none_or(|ext| ext != "toml") {
+                continue;
+            }
+
+            let Some(locale) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            catalogs.insert(normalize_locale(locale), load_catalog(&path)?);
+        }
+
+        let default_locale = normalize_locale(default_locale);
+        if !catalogs.contains_key(&default_locale) {
+            bail!("there is no catalog for the default locale <{default_locale}> in {dir}");
+        }
+
+        tracing::info!("locales loaded: {:?}", catalogs.keys().collect::<Vec<_>>());
+
+        Ok(Self { inner: Arc::new(I18nInner { default_locale, catalogs }) })
+    }
+
+    pub fn default_locale(&self) -> &str {
+        &self.inner.default_locale
+    }
+
+    /// Picks the first candidate backed by a catalog, either as is or by its base language
+    pub fn negotiate<'a>(&self, candidates: impl IntoIterator<Item = &'a str>) -> String {
+        candidates
+            .into_iter()
+            .map(normalize_locale)
+            .find_map(|locale| {
+                if self.inner.catalogs.contains_key(&locale) {
+                    return Some(locale);
+                }
+                let base = base_language(&locale);
+                self.inner.catalogs.contains_key(base).then(|| base.to_string())
+            })
+            .unwrap_or_else(|| self.inner.default_locale.clone())
+    }
+
+    /// Negotiates the locale from an `Accept-Language` header, honoring the `q` weights
+    pub fn negotiate_accept_language(&self, header: &str) -> String {
+        let mut ranges = header
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next()?.trim();
+                let weight = parts
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .and_then(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                (!tag.is_empty() && tag != "*" && weight > 0.0).then_some((tag, weight))
+            })
+            .collect::<Vec<_>>();
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        self.negotiate(ranges.into_iter().map(|(tag, _)| tag))
+    }
+
+    /// Locales to look a message or a template up in, most specific first
+    pub fn fallback_chain(&self, locale: &str) -> Vec<String> {
+        let locale = normalize_locale(locale);
+        let base = base_language(&locale).to_string();
+
+        let mut chain = Vec::with_capacity(3);
+        for candidate in [locale.clone(), base, self.inner.default_locale.clone()] {
+            if !chain.contains(&candidate) {
+                chain.push(candidate);
+            }
+        }
+        chain
+    }
+
+    pub fn translate(&self, locale: &str, key: &str) -> Option<&str> {
+        self.fallback_chain(locale)
+            .iter()
+            .find_map(|locale| self.inner.catalogs.get(locale)?.get(key).map(String::as_str))
+    }
+}
+
+fn load_catalog(path: &Path) -> anyhow::Result<Catalog> {
+    let sections: HashMap<String, HashMap<String, String>> = Config::builder()
+        .add_source(File::from(path).format(FileFormat::Toml))
+        .build()
+        .and_then(Config::try_deserialize)
+        .with_context(|| format!("invalid catalog {}", path.display()))?;
+
+    Ok(sections
+        .into_iter()
+        .flat_map(|(section, messages)| {
+            messages.into_iter().map(move |(key, message)| (format!("{section}.{key}"), message))
+        })
+        .collect())
+}
+
+fn normalize_locale(locale: &str) -> String {
+    locale.trim().replace('_', "-").to_ascii_lowercase()
+}
+
+fn base_language(locale: &str) -> &str {
+    locale.split('-').next().unwrap_or(locale)
+}
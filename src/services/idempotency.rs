@@ -0,0 +1,153 @@
+use std::time::Duration;
+
+use rusqlite::{OptionalExtension, params};
+
+use super::storage::{Storage, unix_now};
+use crate::api::errors::StorageErrors;
+
+/// How long a key stays locked by a request, which never got to complete it
+const IN_FLIGHT_TTL: i64 = 60;
+
+/// Response stored for the repeats of a request
+#[derive(Clone, Debug)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub body: String,
+}
+
+#[derive(Clone, Debug)]
+pub enum Reservation {
+    /// The key is new, the request goes ahead and completes it
+    Reserved,
+    /// The first request with the key is still running
+    InFlight,
+    /// The key was already used with a different payload
+    Mismatch,
+    Completed(StoredResponse),
+}
+
+/// Remembers the outcome of the requests by their `Idempotency-Key` header, a key only counts
+/// within its scope, i.e. the route it was sent to
+#[derive(Clone, Debug)]
+pub struct IdempotencyKeys {
+    storage: Storage,
+    ttl: Duration,
+}
+
+impl IdempotencyKeys {
+    pub fn new(storage: Storage, ttl: u64) -> Self {
+        Self { storage, ttl: Duration::from_secs(ttl) }
+    }
+
+    /// Locks the key for the request, unless another request got there first
+    pub async fn reserve(
+        &self,
+        scope: &str,
+        key: &str,
+        fingerprint: &str,
+    ) -> Result<Reservation, StorageErrors> {
+        let scope = scope.to_string();
+        let key = key.to_string();
+        let fingerprint = fingerprint.to_string();
+
+        self.storage
+            .call(move |conn| {
+                let tx = conn.transaction()?;
+                let now = unix_now();
+
+                tx.execute(
+                    "DELETE FROM idempotency_keys WHERE expires_at <= ?1",
+                    params![now],
+                )?;
+
+                let existing = tx
+                    .query_row(
+                        "SELECT fingerprint, status, content_type, body FROM idempotency_keys
+                         WHERE scope = ?1 AND key = ?2",
+                        params![scope, key],
+                        |row| {
+                            Ok((
+                                row.get::<_, String>(0)?,
+                                row.get::<_, Option<u16>>(1)?,
+                                row.get::<_, Option<String>>(2)?,
+                                row.get::<_, Option<String>>(3)?,
+                            ))
+                        },
+                    )
+                    .optional()?;
+
+                let reservation = match existing {
+                    None => {
+                        tx.execute(
+                            "INSERT INTO idempotency_keys
+                                 (scope, key, fingerprint, created_at, expires_at)
+                             VALUES (?1, ?2, ?3, ?4, ?5)",
+                            params![scope, key, fingerprint, now, now + IN_FLIGHT_TTL],
+                        )?;
+                        Reservation::Reserved
+                    }
+                    Some((stored, ..)) if stored != fingerprint => Reservation::Mismatch,
+                    Some((_, Some(status), content_type, body)) => {
+                        Reservation::Completed(StoredResponse {
+                            status,
+                            content_type,
+                            body: body.unwrap_or_default(),
+                        })
+                    }
+                    Some((_, None, ..)) => Reservation::InFlight,
+                };
+                tx.commit()?;
+
+                Ok(reservation)
+            })
+            .await
+    }
+
+    /// Stores the response, so the repeats get it back until the key expires
+    pub async fn complete(
+        &self,
+        scope: &str,
+        key: &str,
+        response: StoredResponse,
+    ) -> Result<(), StorageErrors> {
+        let scope = scope.to_string();
+        let key = key.to_string();
+        let expires_at = unix_now() + self.ttl.as_secs() as i64;
+
+        self.storage
+            .call(move |conn| {
+                conn.execute(
+                    "UPDATE idempotency_keys
+                     SET status = ?3, content_type = ?4, body = ?5, expires_at = ?6
+                     WHERE scope = ?1 AND key = ?2",
+                    params![
+                        scope,
+                        key,
+                        response.status,
+                        response.content_type,
+                        response.body,
+                        expires_at
+                    ],
+                )?;
+                Ok(())
+            })
+            .await
+    }
+
+    /// Unlocks the key, so the request can be retried with it
+    pub async fn release(&self, scope: &str, key: &str) -> Result<(), StorageErrors> {
+        let scope = scope.to_string();
+        let key = key.to_string();
+
+        self.storage
+            .call(move |conn| {
+                conn.execute(
+                    "DELETE FROM idempotency_keys WHERE scope = ?1 AND key = ?2 AND status IS NULL",
+                    params![scope, key],
+                )?;
+                Ok(())
+            })
+            .await
+    }
+}
@@ -1,129 +1,232 @@
-use std::{str::FromStr, time::Duration};
+use std::{str::FromStr, sync::Arc};
 
 use anyhow::Context;
-use askama::Template;
 use lettre::{
-    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
-    message::{Mailbox, header::ContentType},
+    Message,
+    message::{Mailbox, MultiPart},
 };
 use tokio_retry::{
-    Retry,
+    RetryIf,
     strategy::{ExponentialBackoff, jitter},
 };
 
+use serde::Serialize;
+
+use super::{
+    i18n::I18n,
+    routing::Routing,
+    spam::{SpamAction, SpamFilter},
+    templates::{AUTO_REPLY_HTML, AUTO_REPLY_TEXT, LEAD_HTML, LEAD_TEXT, Templates},
+    transport::MailTransport,
+};
 use crate::{
     api::{errors::EmailErrors, models::LetsStartForm},
     configs::AppConfigs,
 };
 
+const EXCERPT_LENGTH: usize = 48;
+const SPAM_TAG: &str = "[SPAM?]";
+
 #[derive(Clone, Debug)]
 pub struct Mailer {
     from: Mailbox,
-    to: Mailbox,
-    transport: AsyncSmtpTransport<Tokio1Executor>,
+    i18n: I18n,
+    routing: Routing,
+    spam: SpamFilter,
+    templates: Templates,
+    transport: Arc<dyn MailTransport>,
 }
 
 impl Mailer {
-    pub fn new(configs: &AppConfigs) -> anyhow::Result<Self> {
-        let timeout = Some(Duration::from_millis(configs.smtp_connection_timeout));
-        let url = format!(
-            "smtps://{}@{}",
-            configs.smtp_auth.as_str(),
-            configs.smtp_addr.as_str()
-        );
-
-        let transport = AsyncSmtpTransport::<Tokio1Executor>::from_url(url.as_str())
-            .inspect_err(|err| tracing::error!("smtp error: {:?}", err))
-            .context("couldn't create SMTP transport from URL")?
-            .timeout(timeout)
-            .build();
-
+    pub fn new(
+        configs: &AppConfigs,
+        transport: Arc<dyn MailTransport>,
+        templates: Templates,
+        i18n: I18n,
+        spam: SpamFilter,
+    ) -> anyhow::Result<Self> {
         let from = Mailbox::from_str(configs.from_mailbox.as_str())
             .inspect_err(|err| tracing::error!("mailbox error: {:?}", err))
             .context("invalid or incompatible <from>")?;
-        let to = Mailbox::from_str(configs.to_mailbox.as_str())
-            .inspect_err(|err| tracing::error!("mailbox error: {:?}", err))
-            .context("invalid or incompatible <to>")?;
+        let routing = Routing::new(configs)?;
 
-        Ok(Self { from, to, transport })
+        Ok(Self { from, i18n, routing, spam, templates, transport })
     }
 
     pub async fn send_message(
         &self,
-        form: LetsStartForm,
+        form: &LetsStartForm,
+        ticket: i64,
+        configs: &AppConfigs,
+    ) -> Result<(), EmailErrors> {
+        let ctx = LetterContext::new(form, ticket);
+        let (letter_text, letter_html) = self.build_letter(form, &ctx)?;
+        let mut subject = self.templates.render_subject(&ctx)?;
+
+        // Suspicious leads still reach the inbox, but stand out there
+        let verdict = self.spam.verdict(form);
+        if verdict.action >= SpamAction::Tag {
+            tracing::info!("lead #{ticket} tagged as spam, score {:.1}", verdict.score);
+            subject = format!("{SPAM_TAG} {subject}");
+        }
+
+        let recipients = self.routing.recipients(form);
+
+        let mut builder =
+            Message::builder().from(self.from.clone()).reply_to(submitter_mailbox(form)?);
+        for mailbox in recipients.to {
+            builder = builder.to(mailbox);
+        }
+        for mailbox in recipients.cc {
+            builder = builder.cc(mailbox);
+        }
+        for mailbox in recipients.bcc {
+            builder = builder.bcc(mailbox);
+        }
+
+        let message = builder
+            .subject(subject)
+            .multipart(MultiPart::alternative_plain_html(letter_text, letter_html))?;
+
+        self.deliver(&message, configs).await
+    }
+
+    /// Confirms the submission to its author, so they know it reached us
+    pub async fn send_auto_reply(
+        &self,
+        form: &LetsStartForm,
+        ticket: i64,
         configs: &AppConfigs,
     ) -> Result<(), EmailErrors> {
-        let letter_text = self.build_letter_text(&form)?;
+        let locales = self.locales(form);
+        let ctx = LetterContext::new(form, ticket);
+        let (reply_text, reply_html) = self.build_auto_reply(&locales, &ctx)?;
+        // The configured subject is used unless a catalog translates it
+        let subject = self
+            .i18n
+            .translate(&locales[0], "email.auto_reply_subject")
+            .unwrap_or(&configs.auto_reply_subject)
+            .to_string();
 
         let message = Message::builder()
             .from(self.from.clone())
-            .to(self.to.clone())
-            .subject("Let's start".to_string())
-            .header(ContentType::TEXT_PLAIN)
-            .body(letter_text)?;
+            .to(submitter_mailbox(form)?)
+            .subject(subject)
+            .multipart(MultiPart::alternative_plain_html(reply_text, reply_html))?;
+
+        self.deliver(&message, configs).await
+    }
 
+    async fn deliver(&self, message: &Message, configs: &AppConfigs) -> Result<(), EmailErrors> {
         let retry_strategy = ExponentialBackoff::from_millis(configs.retry_timeout)
             .take(configs.retry_count)
             .map(jitter);
 
-        Retry::spawn(retry_strategy, || async {
-            match self.transport.send(message.clone()).await {
-                Ok(_) => Ok(()),
-                Err(cause) => {
-                    tracing::error!("Smtp transport error: {:?}", cause);
-                    Err(cause)
+        RetryIf::start(
+            retry_strategy,
+            || async {
+                match self.transport.send(message).await {
+                    Ok(_) => Ok(()),
+                    Err(cause) => {
+                        tracing::error!("Mail transport error: {:?}", cause);
+                        Err(cause)
+                    }
                 }
-            }
-        })
+            },
+            EmailErrors::is_transient,
+        )
         .await?;
 
         Ok(())
     }
 
-    fn build_letter_text(&self, form: &LetsStartForm) -> Result<String, EmailErrors> {
-        let template = LetsStartEmailTemplate::from(form);
-        Ok(template.render()?)
+    /// Fallback chain of the submitter's locale
+    fn locales(&self, form: &LetsStartForm) -> Vec<String> {
+        let locale = form.locale.as_deref().unwrap_or(self.i18n.default_locale());
+        self.i18n.fallback_chain(locale)
     }
-}
-
-#[derive(Template)]
-#[template(
-    source = r#"
-Hey,
 
-I hope this message finds you well. My name is {{ name }}.
-I would like to discuss a potential collaboration with you on an upcoming project.
+    fn build_letter(
+        &self,
+        form: &LetsStartForm,
+        ctx: &LetterContext<'_>,
+    ) -> Result<(String, String), EmailErrors> {
+        let locales = self.locales(form);
+        let text = self.templates.render(LEAD_TEXT, &locales, ctx)?;
+        let html = self.templates.render(LEAD_HTML, &locales, ctx)?;
+
+        Ok((text, html))
+    }
 
-A brief overview of the project:
-• {{ project_description }}
-• Our budget ranges from {{ min_budget }} to {{ max_budget }} U.S. dollars
+    fn build_auto_reply(
+        &self,
+        locales: &[String],
+        ctx: &LetterContext<'_>,
+    ) -> Result<(String, String), EmailErrors> {
+        let text = self.templates.render(AUTO_REPLY_TEXT, locales, ctx)?;
+        let html = self.templates.render(AUTO_REPLY_HTML, locales, ctx)?;
 
-If you are interested in discussing this opportunity further, please, reach out to me
-at {{ email }} email address.
+        Ok((text, html))
+    }
+}
 
-Looking forward to your response.
+/// Builds the `"<name>" <email>` mailbox of the person who submitted the form
+fn submitter_mailbox(form: &LetsStartForm) -> Result<Mailbox, EmailErrors> {
+    // Control chars (CR/LF above all) are dropped, so the name can't smuggle extra headers in,
+    // quoting and RFC 2047 encoding of whatever is left is up to `lettre`
+    let name = form
+        .name
+        .chars()
+        .map(|char| if char.is_control() { ' ' } else { char })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    let name = (!name.is_empty()).then_some(name);
+
+    Ok(Mailbox::new(name, form.email.trim().parse()?))
+}
 
-Regards.
-"#,
-    ext = "txt",
-    escape = "none"
-)]
-struct LetsStartEmailTemplate<'a> {
+/// Variables available to every email template, the subject one included
+#[derive(Serialize)]
+struct LetterContext<'a> {
     name: &'a str,
+    email: &'a str,
     project_description: &'a str,
+    excerpt: String,
     min_budget: u16,
     max_budget: u16,
-    email: &'a str,
+    ticket: i64,
 }
 
-impl<'a> From<&'a LetsStartForm> for LetsStartEmailTemplate<'a> {
-    fn from(form: &'a LetsStartForm) -> Self {
+impl<'a> LetterContext<'a> {
+    fn new(form: &'a LetsStartForm, ticket: i64) -> Self {
         Self {
             name: &form.name,
+            email: &form.email,
             project_description: &form.project_description,
+            excerpt: excerpt(&form.project_description),
             min_budget: form.min_budget,
             max_budget: form.max_budget,
-            email: &form.email,
+            ticket,
+        }
+    }
+}
+
+/// Cuts the text down to a few words, short enough to fit into a subject line
+fn excerpt(text: &str) -> String {
+    let mut excerpt = String::new();
+
+    for word in text.split_whitespace() {
+        if excerpt.chars().count() + word.chars().count() + 1 > EXCERPT_LENGTH {
+            excerpt.push('…');
+            return excerpt;
         }
+        if !excerpt.is_empty() {
+            excerpt.push(' ');
+        }
+        excerpt.push_str(word);
     }
+
+    excerpt
 }
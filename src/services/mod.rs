@@ -1 +1,22 @@
+pub mod captcha;
+pub mod dead_letters;
+pub mod domain_policy;
+pub mod duplicates;
+pub mod encryption;
+pub mod export;
+pub mod form_token;
+pub mod i18n;
+pub mod idempotency;
 pub mod mailer;
+pub mod notifier;
+pub mod outbox;
+pub mod privacy;
+pub mod quarantine;
+pub mod rate_limit;
+pub mod routing;
+pub mod signing;
+pub mod spam;
+pub mod storage;
+pub mod submissions;
+pub mod templates;
+pub mod transport;
@@ -0,0 +1,690 @@
+use std::{fmt::Debug, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use reqwest::header::CONTENT_TYPE;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::Serialize;
+use tokio::{sync::Notify, task::JoinHandle};
+use tokio_retry::strategy::{ExponentialBackoff, jitter};
+
+use super::{
+    encryption::FieldCipher,
+    privacy::redact_emails,
+    signing::hmac_sha256,
+    storage::{Storage, unix_now, unix_now_millis},
+};
+use crate::{
+    api::{
+        errors::{NotifyErrors, StorageErrors},
+        models::LetsStartForm,
+    },
+    configs::{AppConfigs, Webhook},
+    shutdown::Shutdown,
+};
+
+pub const X_LETS_START_EVENT: &str = "x-lets-start-event";
+pub const X_LETS_START_TIMESTAMP: &str = "x-lets-start-timestamp";
+pub const X_LETS_START_SIGNATURE: &str = "x-lets-start-signature";
+
+/// Sent once a submission is queued for delivery, either directly or out of the quarantine
+pub const SUBMISSION_RECEIVED: &str = "submission.received";
+/// Sent once a dead letter is queued for delivery again
+pub const SUBMISSION_REPLAYED: &str = "submission.replayed";
+
+const BATCH_SIZE: usize = 16;
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Event body sent to a notifier
+#[derive(Clone, Debug)]
+pub struct Notification {
+    pub event: String,
+    /// JSON, built from the stored submission at the delivery time
+    pub body: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SubmissionEvent<'a> {
+    event: &'a str,
+    submission_id: i64,
+    ticket: i64,
+    submission: &'a LetsStartForm,
+}
+
+/// Channel besides the lead email, told about every accepted submission
+#[async_trait]
+pub trait Notifier: Debug + Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Delay before the given retry, nothing once the retries are used up
+    fn retry_delay(&self, retry: u32) -> Option<Duration>;
+
+    /// Makes a single delivery attempt, the retries are scheduled by the worker
+    async fn notify(&self, notification: &Notification) -> Result<(), NotifyErrors>;
+}
+
+/// POSTs the event as JSON, signed with HMAC-SHA256 over `<timestamp>.<body>`
+#[derive(Clone)]
+pub struct WebhookNotifier {
+    client: reqwest::Client,
+    name: String,
+    url: String,
+    secret: String,
+    retry_count: usize,
+    retry_timeout: u64,
+}
+
+impl Debug for WebhookNotifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebhookNotifier").field("name", &self.name).finish_non_exhaustive()
+    }
+}
+
+impl WebhookNotifier {
+    pub fn new(webhook: &Webhook) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(webhook.timeout))
+            .build()
+            .context("couldn't create the webhook HTTP client")?;
+
+        Ok(Self {
+            client,
+            name: webhook.name.clone(),
+            url: webhook.url.clone(),
+            secret: webhook.secret.clone(),
+            retry_count: webhook.retry_count,
+            retry_timeout: webhook.retry_timeout,
+        })
+    }
+
+    /// Lowercase hex, prefixed with the scheme, so the receivers can tell it apart later
+    fn sign(&self, timestamp: i64, body: &str) -> String {
+        let signature = hmac_sha256(
+            self.secret.as_bytes(),
+            format!("{timestamp}.{body}").as_bytes(),
+        );
+
+        signature.iter().fold(String::from("sha256="), |mut hex, byte| {
+            hex.push_str(&format!("{byte:02x}"));
+            hex
+        })
+    }
+
+    async fn post(&self, notification: &Notification) -> Result<(), NotifyErrors> {
+        // Every attempt is signed anew, so the receivers may reject the stale timestamps
+        let timestamp = unix_now();
+
+        let response = self
+            .client
+            .post(&self.url)
+            .header(CONTENT_TYPE, "application/json")
+            .header(X_LETS_START_EVENT, &notification.event)
+            .header(X_LETS_START_TIMESTAMP, timestamp)
+            .header(
+                X_LETS_START_SIGNATURE,
+                self.sign(timestamp, &notification.body),
+            )
+            .body(notification.body.clone())
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(NotifyErrors::UnexpectedStatus(status));
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn retry_delay(&self, retry: u32) -> Option<Duration> {
+        // The bound doubles on every retry, starting from `retry_timeout`, and the jitter picks
+        // the actual delay at random below it
+        ExponentialBackoff::from_millis(2)
+            .factor(self.retry_timeout / 2)
+            .take(self.retry_count)
+            .map(jitter)
+            .nth((retry as usize).checked_sub(1)?)
+    }
+
+    async fn notify(&self, notification: &Notification) -> Result<(), NotifyErrors> {
+        self.post(notification).await
+    }
+}
+
+#[derive(Debug)]
+struct Delivery {
+    id: i64,
+    webhook: String,
+    submission_id: i64,
+    event: String,
+    attempts: u32,
+}
+
+/// Queues the events for every configured notifier within the callers' transactions
+#[derive(Clone, Debug)]
+pub struct WebhookQueue {
+    names: Arc<[String]>,
+    notify: Arc<Notify>,
+}
+
+impl WebhookQueue {
+    /// Queues one delivery of the event per notifier within the caller's transaction
+    pub(super) fn insert(
+        &self,
+        conn: &Connection,
+        submission_id: i64,
+        event: &str,
+    ) -> rusqlite::Result<()> {
+        let mut stmt = conn.prepare_cached(
+            "INSERT INTO webhook_deliveries
+                 (webhook, submission_id, event, next_attempt_at_ms, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?5)",
+        )?;
+        for name in self.names.iter() {
+            stmt.execute(params![
+                name,
+                submission_id,
+                event,
+                unix_now_millis(),
+                unix_now()
+            ])?;
+        }
+
+        Ok(())
+    }
+
+    /// Wakes the worker up, e.g. after the deliveries were queued behind its back
+    pub fn wake(&self) {
+        if !self.names.is_empty() {
+            self.notify.notify_one();
+        }
+    }
+}
+
+/// Delivers the queued events to the notifiers in the background, so they never hold the
+/// request up and survive the restarts
+#[derive(Clone, Debug)]
+pub struct Notifiers {
+    storage: Storage,
+    cipher: FieldCipher,
+    notifiers: Arc<[Arc<dyn Notifier>]>,
+    queue: WebhookQueue,
+}
+
+impl Notifiers {
+    pub fn new(
+        configs: &AppConfigs,
+        storage: Storage,
+        cipher: FieldCipher,
+    ) -> anyhow::Result<Self> {
+        let notifiers = configs
+            .webhooks
+            .iter()
+            .map(|webhook| Ok(Arc::new(WebhookNotifier::new(webhook)?) as Arc<dyn Notifier>))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Self::with_notifiers(storage, cipher, notifiers))
+    }
+
+    fn with_notifiers(
+        storage: Storage,
+        cipher: FieldCipher,
+        notifiers: Vec<Arc<dyn Notifier>>,
+    ) -> Self {
+        let queue = WebhookQueue {
+            names: notifiers.iter().map(|notifier| notifier.name().to_string()).collect(),
+            notify: Arc::new(Notify::new()),
+        };
+
+        Self { storage, cipher, notifiers: notifiers.into(), queue }
+    }
+
+    pub fn queue(&self) -> WebhookQueue {
+        self.queue.clone()
+    }
+
+    /// Runs the worker until the shutdown, letting the delivery at hand finish first
+    pub fn spawn_worker(self, shutdown: &Shutdown) -> Option<JoinHandle<()>> {
+        if self.notifiers.is_empty() {
+            return None;
+        }
+        let shutdown = shutdown.clone();
+
+        Some(shutdown.clone().spawn(async move {
+            tracing::info!("notifier worker started");
+
+            while !shutdown.is_cancelled() {
+                let idle_timeout = match self.deliver_due(&shutdown).await {
+                    Ok(idle_timeout) => idle_timeout,
+                    Err(err) => {
+                        tracing::error!("notifier error: {:?}", err);
+                        sentry::capture_error(&err);
+                        IDLE_TIMEOUT
+                    }
+                };
+
+                tokio::select! {
+                    _ = self.queue.notify.notified() => {}
+                    _ = tokio::time::sleep(idle_timeout) => {}
+                    _ = shutdown.cancelled() => {}
+                }
+            }
+
+            tracing::info!("notifier worker stopped");
+        }))
+    }
+
+    /// Delivers all due events and returns how long the worker may sleep afterwards
+    async fn deliver_due(&self, shutdown: &Shutdown) -> Result<Duration, StorageErrors> {
+        // The deliveries left behind stay pending until the next start
+        while !shutdown.is_cancelled() {
+            let deliveries = self.due_deliveries().await?;
+            if deliveries.is_empty() {
+                break;
+            }
+
+            for delivery in deliveries {
+                if shutdown.is_cancelled() {
+                    break;
+                }
+                self.deliver(delivery).await?;
+            }
+        }
+
+        let next_attempt_at_ms = self
+            .storage
+            .call(|conn| {
+                let next_attempt_at_ms = conn.query_row(
+                    "SELECT MIN(next_attempt_at_ms) FROM webhook_deliveries
+                     WHERE status = 'pending'",
+                    [],
+                    |row| row.get::<_, Option<i64>>(0),
+                )?;
+                Ok(next_attempt_at_ms)
+            })
+            .await?;
+
+        Ok(next_attempt_at_ms
+            .map(|at| Duration::from_millis(at.saturating_sub(unix_now_millis()).max(1) as u64))
+            .map_or(IDLE_TIMEOUT, |timeout| timeout.min(IDLE_TIMEOUT)))
+    }
+
+    async fn deliver(&self, delivery: Delivery) -> Result<(), StorageErrors> {
+        let Some(notifier) =
+            self.notifiers.iter().find(|notifier| notifier.name() == delivery.webhook)
+        else {
+            let last_error = format!("<{}> is no longer configured", delivery.webhook);
+            return self.fail(&delivery, delivery.attempts, last_error).await;
+        };
+
+        let notification = match self.notification(&delivery).await {
+            Ok(Some(notification)) => notification,
+            Ok(None) => {
+                tracing::info!(
+                    "webhook delivery #{} dropped, submission #{} is gone",
+                    delivery.id,
+                    delivery.submission_id
+                );
+                return self.complete(delivery.id).await;
+            }
+            Err(err @ (StorageErrors::CryptoErrors(_) | StorageErrors::JsonError(_))) => {
+                tracing::error!(
+                    "webhook delivery #{} has a broken payload: {:?}",
+                    delivery.id,
+                    err
+                );
+                return self.fail(&delivery, delivery.attempts, err.to_string()).await;
+            }
+            Err(err) => return Err(err),
+        };
+
+        match notifier.notify(&notification).await {
+            Ok(()) => {
+                tracing::info!(
+                    "submission #{} notified to <{}>",
+                    delivery.submission_id,
+                    delivery.webhook
+                );
+                self.complete(delivery.id).await
+            }
+            Err(err) => {
+                let attempts = delivery.attempts + 1;
+                tracing::warn!(
+                    "webhook delivery #{} to <{}> attempt {} failed: {:?}",
+                    delivery.id,
+                    delivery.webhook,
+                    attempts,
+                    err
+                );
+
+                match notifier.retry_delay(attempts).filter(|_| err.is_transient()) {
+                    Some(delay) => {
+                        self.reschedule(delivery.id, attempts, delay, err.to_string()).await
+                    }
+                    None => {
+                        sentry::capture_error(&err);
+                        self.fail(&delivery, attempts, err.to_string()).await
+                    }
+                }
+            }
+        }
+    }
+
+    /// Builds the event body from the stored submission, nothing if it was erased meanwhile
+    async fn notification(
+        &self,
+        delivery: &Delivery,
+    ) -> Result<Option<Notification>, StorageErrors> {
+        let submission_id = delivery.submission_id;
+
+        let stored = self
+            .storage
+            .call(move |conn| {
+                let stored = conn
+                    .query_row(
+                        "SELECT ticket, payload FROM submissions
+                         WHERE id = ?1 AND ticket IS NOT NULL",
+                        params![submission_id],
+                        |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)),
+                    )
+                    .optional()?;
+                Ok(stored)
+            })
+            .await?;
+        let Some((ticket, payload)) = stored else {
+            return Ok(None);
+        };
+
+        let form = self.cipher.open_form(&payload)?;
+        let event =
+            SubmissionEvent { event: &delivery.event, submission_id, ticket, submission: &form };
+        let body = serde_json::to_string(&event)?;
+
+        Ok(Some(Notification { event: delivery.event.clone(), body }))
+    }
+
+    async fn due_deliveries(&self) -> Result<Vec<Delivery>, StorageErrors> {
+        self.storage
+            .call(|conn| {
+                let mut stmt = conn.prepare_cached(
+                    "SELECT id, webhook, submission_id, event, attempts FROM webhook_deliveries
+                     WHERE status = 'pending' AND next_attempt_at_ms <= ?1
+                     ORDER BY next_attempt_at_ms, id LIMIT ?2",
+                )?;
+                let deliveries = stmt
+                    .query_map(params![unix_now_millis(), BATCH_SIZE], |row| {
+                        Ok(Delivery {
+                            id: row.get(0)?,
+                            webhook: row.get(1)?,
+                            submission_id: row.get(2)?,
+                            event: row.get(3)?,
+                            attempts: row.get(4)?,
+                        })
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(deliveries)
+            })
+            .await
+    }
+
+    async fn complete(&self, id: i64) -> Result<(), StorageErrors> {
+        self.storage
+            .call(move |conn| {
+                conn.execute("DELETE FROM webhook_deliveries WHERE id = ?1", params![id])?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn reschedule(
+        &self,
+        id: i64,
+        attempts: u32,
+        delay: Duration,
+        last_error: String,
+    ) -> Result<(), StorageErrors> {
+        self.storage
+            .call(move |conn| {
+                conn.execute(
+                    "UPDATE webhook_deliveries
+                     SET attempts = ?2, last_error = ?3, next_attempt_at_ms = ?4, updated_at = ?5
+                     WHERE id = ?1",
+                    params![
+                        id,
+                        attempts,
+                        redact_emails(&last_error),
+                        unix_now_millis() + delay.as_millis() as i64,
+                        unix_now()
+                    ],
+                )?;
+                Ok(())
+            })
+            .await
+    }
+
+    /// Keeps the delivery as failed for inspection, it is never attempted again
+    async fn fail(
+        &self,
+        delivery: &Delivery,
+        attempts: u32,
+        last_error: String,
+    ) -> Result<(), StorageErrors> {
+        let id = delivery.id;
+        tracing::error!(
+            "submission #{} notification to <{}> gave up after {} attempts",
+            delivery.submission_id,
+            delivery.webhook,
+            attempts
+        );
+
+        self.storage
+            .call(move |conn| {
+                conn.execute(
+                    "UPDATE webhook_deliveries
+                     SET status = 'failed', attempts = ?2, last_error = ?3, updated_at = ?4
+                     WHERE id = ?1",
+                    params![id, attempts, redact_emails(&last_error), unix_now()],
+                )?;
+                Ok(())
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        net::{IpAddr, Ipv4Addr},
+        sync::Mutex,
+    };
+
+    use axum::{Router, body::Bytes, extract::State, http::HeaderMap, routing::post};
+    use reqwest::StatusCode;
+    use serde_json::json;
+
+    use super::*;
+    use crate::services::{outbox::Outbox, submissions::SubmissionOrigin};
+
+    const SECRET: &str = "webhook_secret_0123456789abcdef_0123";
+
+    #[derive(Debug)]
+    struct Received {
+        headers: HeaderMap,
+        body: String,
+    }
+
+    /// Answers with the scripted statuses in turn, then with 200
+    #[derive(Clone, Default)]
+    struct Receiver {
+        statuses: Arc<Mutex<VecDeque<StatusCode>>>,
+        received: Arc<Mutex<Vec<Received>>>,
+    }
+
+    async fn receive(
+        State(receiver): State<Receiver>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> StatusCode {
+        let body = String::from_utf8(body.to_vec()).expect("the body is UTF-8");
+        receiver.received.lock().unwrap().push(Received { headers, body });
+        receiver.statuses.lock().unwrap().pop_front().unwrap_or(StatusCode::OK)
+    }
+
+    async fn spawn_receiver(statuses: &[StatusCode]) -> (Webhook, Receiver) {
+        let receiver = Receiver {
+            statuses: Arc::new(Mutex::new(statuses.iter().copied().collect())),
+            ..Default::default()
+        };
+        let app = Router::new().route("/hook", post(receive)).with_state(receiver.clone());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let webhook = Webhook {
+            name: "crm".to_string(),
+            url: format!("http://{addr}/hook"),
+            secret: SECRET.to_string(),
+            retry_count: 3,
+            retry_timeout: 100,
+            timeout: 1000,
+        };
+
+        (webhook, receiver)
+    }
+
+    /// Queues a submission the way the handler does and runs the worker for it
+    async fn deliver(webhook: &Webhook, expected: usize, receiver: &Receiver) -> Storage {
+        let configs = AppConfigs::for_tests(&[]);
+        let storage = Storage::open(":memory:").unwrap();
+        let cipher = FieldCipher::new(&configs).unwrap();
+        let notifiers = Notifiers::with_notifiers(
+            storage.clone(),
+            cipher.clone(),
+            vec![Arc::new(WebhookNotifier::new(webhook).unwrap())],
+        );
+        let outbox = Outbox::new(storage.clone(), cipher, notifiers.queue());
+
+        let form = serde_json::from_value(json!({
+            "email": "jane@example.com",
+            "minBudget": 1000,
+            "maxBudget": 5000,
+            "name": "Jane Doe",
+            "projectDescription": "We need a backend service for our new product, with an API and a database.",
+        }))
+        .unwrap();
+        let origin = SubmissionOrigin {
+            request_id: None,
+            client_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            origin: None,
+        };
+        outbox.enqueue(&form, origin, None).await.unwrap().expect("the submission is queued");
+
+        let shutdown = Shutdown::new();
+        notifiers.spawn_worker(&shutdown).expect("the worker runs for a webhook");
+        for _ in 0..100 {
+            if receiver.received.lock().unwrap().len() >= expected {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        // Gives the worker a moment to record the outcome, or to make an unexpected attempt
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        shutdown.drain(Duration::from_secs(1)).await;
+
+        storage
+    }
+
+    async fn delivery_rows(storage: &Storage) -> Vec<(String, u32)> {
+        storage
+            .call(|conn| {
+                let mut stmt =
+                    conn.prepare("SELECT status, attempts FROM webhook_deliveries ORDER BY id")?;
+                let rows = stmt
+                    .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(rows)
+            })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn signs_the_timestamp_and_the_body() {
+        let (webhook, receiver) = spawn_receiver(&[]).await;
+        let notifier = WebhookNotifier::new(&webhook).unwrap();
+        let notification = Notification {
+            event: SUBMISSION_RECEIVED.to_string(),
+            body: r#"{"ok":true}"#.to_string(),
+        };
+
+        notifier.notify(&notification).await.unwrap();
+
+        let received = receiver.received.lock().unwrap();
+        let [Received { headers, body }] = received.as_slice() else {
+            panic!("expected one request, got {received:?}");
+        };
+        let header = |name: &str| headers.get(name).unwrap().to_str().unwrap().to_string();
+        let timestamp = header(X_LETS_START_TIMESTAMP);
+        let expected = hmac_sha256(SECRET.as_bytes(), format!("{timestamp}.{body}").as_bytes())
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+
+        assert_eq!(body, r#"{"ok":true}"#);
+        assert_eq!(header("content-type"), "application/json");
+        assert_eq!(header(X_LETS_START_EVENT), SUBMISSION_RECEIVED);
+        assert!(timestamp.parse::<i64>().unwrap().abs_diff(unix_now()) <= 5);
+        assert_eq!(header(X_LETS_START_SIGNATURE), format!("sha256={expected}"));
+    }
+
+    #[tokio::test]
+    async fn retries_the_server_errors_and_the_rate_limits() {
+        let statuses = [StatusCode::INTERNAL_SERVER_ERROR, StatusCode::TOO_MANY_REQUESTS];
+        let (webhook, receiver) = spawn_receiver(&statuses).await;
+
+        let storage = deliver(&webhook, 3, &receiver).await;
+
+        let event = {
+            let received = receiver.received.lock().unwrap();
+            assert_eq!(received.len(), 3);
+            serde_json::from_str::<serde_json::Value>(&received[2].body).unwrap()
+        };
+        assert_eq!(event["event"], SUBMISSION_RECEIVED);
+        assert_eq!(event["submission"]["email"], "jane@example.com");
+        assert!(
+            delivery_rows(&storage).await.is_empty(),
+            "the delivery is done"
+        );
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_the_other_client_errors() {
+        let (webhook, receiver) = spawn_receiver(&[StatusCode::BAD_REQUEST]).await;
+
+        let storage = deliver(&webhook, 1, &receiver).await;
+
+        assert_eq!(receiver.received.lock().unwrap().len(), 1);
+        assert_eq!(delivery_rows(&storage).await, [("failed".to_string(), 1)]);
+    }
+
+    #[tokio::test]
+    async fn gives_up_once_the_retries_are_used_up() {
+        let statuses = [StatusCode::BAD_GATEWAY; 4];
+        let (webhook, receiver) = spawn_receiver(&statuses).await;
+
+        let storage = deliver(&webhook, 4, &receiver).await;
+
+        assert_eq!(receiver.received.lock().unwrap().len(), 4);
+        assert_eq!(delivery_rows(&storage).await, [("failed".to_string(), 4)]);
+    }
+}
@@ -0,0 +1,453 @@
+use std::{sync::Arc, time::Duration};
+
+use rusqlite::{Connection, params};
+use tokio::{sync::Notify, task::JoinHandle};
+
+use super::{
+    duplicates::FingerprintClaim,
+    encryption::{FieldCipher, SealedForm},
+    mailer::Mailer,
+    notifier::{SUBMISSION_RECEIVED, WebhookQueue},
+    privacy::redact_emails,
+    storage::{Storage, unix_now},
+    submissions::{self, SubmissionOrigin, SubmissionStatus},
+};
+use crate::{
+    api::{errors::StorageErrors, models::LetsStartForm},
+    configs::AppConfigs,
+    shutdown::Shutdown,
+};
+
+const BATCH_SIZE: usize = 16;
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct OutboxJob {
+    id: i64,
+    ticket: i64,
+    payload: String,
+    attempts: u32,
+}
+
+/// Submission stored along with its outbox job
+#[derive(Clone, Copy, Debug)]
+pub struct Queued {
+    pub submission_id: i64,
+    pub ticket: i64,
+}
+
+/// Durable queue of submissions waiting to be delivered by the background worker
+#[derive(Clone, Debug)]
+pub struct Outbox {
+    storage: Storage,
+    cipher: FieldCipher,
+    webhooks: WebhookQueue,
+    notify: Arc<Notify>,
+}
+
+impl Outbox {
+    pub fn new(storage: Storage, cipher: FieldCipher, webhooks: WebhookQueue) -> Self {
+        Self { storage, cipher, webhooks, notify: Arc::new(Notify::new()) }
+    }
+
+    /// Stores the submission and queues its form and webhook events in one go, so none exists
+    /// without the others, returns nothing for a duplicate of the claimed fingerprint
+    pub async fn enqueue(
+        &self,
+        form: &LetsStartForm,
+        origin: SubmissionOrigin,
+        claim: Option<FingerprintClaim>,
+    ) -> Result<Option<Queued>, StorageErrors> {
+        let form = self.cipher.seal_form(form)?;
+        let webhooks = self.webhooks.clone();
+
+        let queued = self
+            .storage
+            .call(move |conn| {
+                let tx = conn.transaction()?;
+                // The fingerprint only sticks along with the submission, so a failed one may retry
+                if let Some(claim) = claim
+                    && claim.record(&tx)?
+                {
+                    return Ok(None);
+                }
+
+                let submission_id =
+                    submissions::insert(&tx, &form, &origin, SubmissionStatus::Received)?;
+                let ticket = insert(&tx, &form, None)?;
+                tx.execute(
+                    "UPDATE submissions SET ticket = ?1 WHERE id = ?2",
+                    params![ticket, submission_id],
+                )?;
+                webhooks.insert(&tx, submission_id, SUBMISSION_RECEIVED)?;
+                tx.commit()?;
+                Ok(Some(Queued { submission_id, ticket }))
+            })
+            .await?;
+
+        if queued.is_some() {
+            self.wake();
+        }
+
+        Ok(queued)
+    }
+
+    /// Wakes the workers up, e.g. after a job was queued behind their back
+    pub fn wake(&self) {
+        self.notify.notify_one();
+        self.webhooks.wake();
+    }
+
+    /// Runs the worker until the shutdown, letting the job at hand finish first
+    pub fn spawn_worker(
+        self,
+        mailer: Mailer,
+        configs: AppConfigs,
+        shutdown: &Shutdown,
+    ) -> JoinHandle<()> {
+        let shutdown = shutdown.clone();
+
+        shutdown.clone().spawn(async move {
+            tracing::info!("outbox worker started");
+
+            while !shutdown.is_cancelled() {
+                let idle_timeout = match self.deliver_due(&mailer, &configs, &shutdown).await {
+                    Ok(idle_timeout) => idle_timeout,
+                    Err(err) => {
+                        tracing::error!("outbox error: {:?}", err);
+                        sentry::capture_error(&err);
+                        Duration::from_secs(configs.outbox_retry_timeout)
+                    }
+                };
+
+                tokio::select! {
+                    _ = self.notify.notified() => {}
+                    _ = tokio::time::sleep(idle_timeout) => {}
+                    _ = shutdown.cancelled() => {}
+                }
+            }
+
+            tracing::info!("outbox worker stopped");
+        })
+    }
+
+    /// Delivers all due jobs and returns how long the worker may sleep afterwards
+    async fn deliver_due(
+        &self,
+        mailer: &Mailer,
+        configs: &AppConfigs,
+        shutdown: &Shutdown,
+    ) -> Result<Duration, StorageErrors> {
+        // The jobs left behind stay pending until the next start
+        while !shutdown.is_cancelled() {
+            let jobs = self.due_jobs().await?;
+            if jobs.is_empty() {
+                break;
+            }
+
+            for job in jobs {
+                if shutdown.is_cancelled() {
+                    break;
+                }
+                self.deliver(job, mailer, configs).await?;
+            }
+        }
+
+        let next_attempt_at = self
+            .storage
+            .call(|conn| {
+                let next_attempt_at = conn.query_row(
+                    "SELECT MIN(next_attempt_at) FROM outbox WHERE status = 'pending'",
+                    [],
+                    |row| row.get::<_, Option<i64>>(0),
+                )?;
+                Ok(next_attempt_at)
+            })
+            .await?;
+
+        Ok(next_attempt_at
+            .map(|at| Duration::from_secs(at.saturating_sub(unix_now()).max(1) as u64))
+            .map_or(IDLE_TIMEOUT, |timeout| timeout.min(IDLE_TIMEOUT)))
+    }
+
+    async fn deliver(
+        &self,
+        job: OutboxJob,
+        mailer: &Mailer,
+        configs: &AppConfigs,
+    ) -> Result<(), StorageErrors> {
+        let form = match self.cipher.open_form(&job.payload) {
+            Ok(form) => form,
+            Err(err) => {
+                tracing::error!("outbox job #{} has a broken payload: {:?}", job.id, err);
+                return self.fail(&job, job.attempts, err.to_string()).await;
+            }
+        };
+
+        match mailer.send_message(&form, job.ticket, configs).await {
+            Ok(()) => {
+                tracing::info!("outbox job #{} delivered", job.id);
+                self.complete(&job).await?;
+
+                // The auto-reply is a courtesy, so its failure must never fail the lead
+                if configs.auto_reply_enabled
+                    && let Err(err) = mailer.send_auto_reply(&form, job.ticket, configs).await
+                {
+                    tracing::warn!("outbox job #{} auto-reply failed: {:?}", job.id, err);
+                    sentry::capture_error(&err);
+                }
+
+                Ok(())
+            }
+            Err(err) => {
+                let attempts = job.attempts + 1;
+                tracing::warn!(
+                    "outbox job #{} attempt {} failed: {:?}",
+                    job.id,
+                    attempts,
+                    err
+                );
+
+                // A permanent failure goes to the dead letters without the pointless retries
+                if !err.is_transient() || attempts >= configs.outbox_retry_count {
+                    sentry::capture_error(&err);
+                    return self.fail(&job, attempts, err.to_string()).await;
+                }
+
+                let backoff = backoff_secs(attempts, configs);
+                self.reschedule(job.id, attempts, backoff, err.to_string()).await
+            }
+        }
+    }
+
+    async fn due_jobs(&self) -> Result<Vec<OutboxJob>, StorageErrors> {
+        self.storage
+            .call(|conn| {
+                let mut stmt = conn.prepare_cached(
+                    "SELECT id, COALESCE(ticket, id), payload, attempts FROM outbox
+                     WHERE status = 'pending' AND next_attempt_at <= ?1
+                     ORDER BY next_attempt_at, id LIMIT ?2",
+                )?;
+                let jobs = stmt
+                    .query_map(params![unix_now(), BATCH_SIZE], |row| {
+                        Ok(OutboxJob {
+                            id: row.get(0)?,
+                            ticket: row.get(1)?,
+                            payload: row.get(2)?,
+                            attempts: row.get(3)?,
+                        })
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(jobs)
+            })
+            .await
+    }
+
+    async fn complete(&self, job: &OutboxJob) -> Result<(), StorageErrors> {
+        let (id, ticket) = (job.id, job.ticket);
+
+        self.storage
+            .call(move |conn| {
+                let tx = conn.transaction()?;
+                tx.execute("DELETE FROM outbox WHERE id = ?1", params![id])?;
+                submissions::set_status(&tx, ticket, SubmissionStatus::Sent, None)?;
+                tx.commit()?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn reschedule(
+        &self,
+        id: i64,
+        attempts: u32,
+        backoff: u64,
+        last_error: String,
+    ) -> Result<(), StorageErrors> {
+        self.storage
+            .call(move |conn| {
+                let now = unix_now();
+                conn.execute(
+                    "UPDATE outbox
+                     SET attempts = ?2, last_error = ?3, next_attempt_at = ?4, updated_at = ?5
+                     WHERE id = ?1",
+                    params![id, attempts, redact_emails(&last_error), now + backoff as i64, now],
+                )?;
+                Ok(())
+            })
+            .await
+    }
+
+    /// Moves the job into the dead-letter store
+    async fn fail(
+        &self,
+        job: &OutboxJob,
+        attempts: u32,
+        last_error: String,
+    ) -> Result<(), StorageErrors> {
+        let (id, ticket) = (job.id, job.ticket);
+        tracing::error!("outbox job #{id} gave up after {attempts} attempts");
+        let last_error = redact_emails(&last_error).into_owned();
+
+        self.storage
+            .call(move |conn| {
+                let tx = conn.transaction()?;
+                tx.execute(
+                    "INSERT INTO dead_letters
+                         (outbox_id, ticket, payload, email_index, last_error, attempts,
+                          created_at, failed_at)
+                     SELECT id, ticket, payload, email_index, ?2, ?3, created_at, ?4
+                     FROM outbox WHERE id = ?1",
+                    params![id, last_error, attempts, unix_now()],
+                )?;
+                tx.execute("DELETE FROM outbox WHERE id = ?1", params![id])?;
+                submissions::set_status(&tx, ticket, SubmissionStatus::Failed, Some(&last_error))?;
+                tx.commit()?;
+                Ok(())
+            })
+            .await
+    }
+}
+
+/// Queues a job within the caller's transaction and returns its id, a new job becomes its own
+/// ticket, a replayed one keeps the ticket it had
+pub(super) fn insert(
+    conn: &Connection,
+    form: &SealedForm,
+    ticket: Option<i64>,
+) -> rusqlite::Result<i64> {
+    let now = unix_now();
+    conn.execute(
+        "INSERT INTO outbox (ticket, payload, email_index, next_attempt_at, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?4, ?4)",
+        params![ticket, form.payload, form.email_index, now],
+    )?;
+
+    let id = conn.last_insert_rowid();
+    conn.execute(
+        "UPDATE outbox SET ticket = ?1 WHERE id = ?1 AND ticket IS NULL",
+        params![id],
+    )?;
+
+    Ok(id)
+}
+
+/// Exponential backoff in seconds, capped by `outbox_retry_max_timeout`
+fn backoff_secs(attempts: u32, configs: &AppConfigs) -> u64 {
+    let factor = 2u64.saturating_pow(attempts.saturating_sub(1));
+    configs.outbox_retry_timeout.saturating_mul(factor).min(configs.outbox_retry_max_timeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use lettre::{Address, Message};
+
+    use super::*;
+    use crate::{
+        api::errors::EmailErrors,
+        services::{
+            i18n::I18n, notifier::Notifiers, spam::SpamFilter, templates::Templates,
+            transport::MailTransport,
+        },
+    };
+
+    /// Fails every send with the error the closure makes
+    #[derive(Debug)]
+    struct FailingTransport(fn() -> EmailErrors);
+
+    #[async_trait]
+    impl MailTransport for FailingTransport {
+        async fn send(&self, _message: &Message) -> Result<(), EmailErrors> {
+            Err((self.0)())
+        }
+    }
+
+    async fn deliver_once(error: fn() -> EmailErrors) -> (Storage, Queued) {
+        let configs = AppConfigs::for_tests(&[]);
+        let storage = Storage::open(":memory:").unwrap();
+        let cipher = FieldCipher::new(&configs).unwrap();
+        let webhooks = Notifiers::new(&configs, storage.clone(), cipher.clone()).unwrap().queue();
+        let outbox = Outbox::new(storage.clone(), cipher, webhooks);
+
+        let mailer = Mailer::new(
+            &configs,
+            Arc::new(FailingTransport(error)),
+            Templates::load(&configs.templates_dir, &configs.subject_template).unwrap(),
+            I18n::load(&configs.locales_dir, &configs.default_locale).unwrap(),
+            SpamFilter::new(&configs).unwrap(),
+        )
+        .unwrap();
+
+        let form = serde_json::from_value(serde_json::json!({
+            "email": "jane@example.com",
+            "minBudget": 1000,
+            "maxBudget": 2000,
+            "name": "Jane",
+            "projectDescription": "A marketing site for a small bakery, with an online order form.",
+        }))
+        .unwrap();
+        let origin =
+            SubmissionOrigin { request_id: None, client_ip: [192, 0, 2, 1].into(), origin: None };
+        let queued = outbox.enqueue(&form, origin, None).await.unwrap().unwrap();
+
+        outbox.deliver_due(&mailer, &configs, &Shutdown::new()).await.unwrap();
+
+        (storage, queued)
+    }
+
+    async fn counts(storage: &Storage) -> (i64, i64, String) {
+        storage
+            .call(|conn| {
+                Ok(conn.query_row(
+                    "SELECT (SELECT COUNT(*) FROM outbox), (SELECT COUNT(*) FROM dead_letters),
+                            (SELECT status FROM submissions)",
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )?)
+            })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn dead_letters_a_permanent_failure_right_away() {
+        let rejected_address =
+            || EmailErrors::AddressError("not an address".parse::<Address>().unwrap_err());
+        let (storage, queued) = deliver_once(rejected_address).await;
+
+        assert_eq!(counts(&storage).await, (0, 1, "failed".to_string()));
+        let (ticket, attempts) = storage
+            .call(|conn| {
+                Ok(
+                    conn.query_row("SELECT ticket, attempts FROM dead_letters", [], |row| {
+                        Ok((row.get::<_, i64>(0)?, row.get::<_, u32>(1)?))
+                    })?,
+                )
+            })
+            .await
+            .unwrap();
+        assert_eq!(ticket, queued.ticket);
+        assert_eq!(attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn retries_a_transient_failure() {
+        let connection_reset = || EmailErrors::IoError(std::io::Error::other("connection reset"));
+        let (storage, _) = deliver_once(connection_reset).await;
+
+        assert_eq!(counts(&storage).await, (1, 0, "received".to_string()));
+        let (attempts, next_attempt_at) = storage
+            .call(|conn| {
+                Ok(
+                    conn.query_row("SELECT attempts, next_attempt_at FROM outbox", [], |row| {
+                        Ok((row.get::<_, u32>(0)?, row.get::<_, i64>(1)?))
+                    })?,
+                )
+            })
+            .await
+            .unwrap();
+        assert_eq!(attempts, 1);
+        assert!(next_attempt_at > unix_now());
+    }
+}
@@ -0,0 +1,740 @@
+use std::{borrow::Cow, sync::LazyLock, time::Duration};
+
+use regex::Regex;
+use rusqlite::{Row, Transaction, params};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio::task::JoinHandle;
+
+use super::{
+    duplicates::fingerprint,
+    encryption::FieldCipher,
+    storage::{Storage, datetime, unix_now},
+};
+use crate::{api::errors::StorageErrors, configs::AppConfigs, shutdown::Shutdown};
+
+const ERASURE_COLUMNS: &str = "id, kind, email_index, reason, submissions, outbox, dead_letters, \
+                               quarantine, created_at";
+
+/// Matches the encrypted rows by the blind index, the older plaintext ones by the payload
+const BY_EMAIL: &str = "email_index = ?2 OR lower(trim(json_extract(payload, '$.email'))) = ?1";
+
+/// Tables holding a form payload, cleared by the erasures and re-encrypted by the rotation
+const PAYLOAD_TABLES: &[&str] = &["submissions", "outbox", "dead_letters", "quarantine"];
+
+/// Rows re-encrypted per round trip by the key rotation
+const ROTATION_BATCH_SIZE: u32 = 200;
+
+static EMAIL_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"[\w.+-]+@[\w-]+(?:\.[\w-]+)+").expect("the email pattern is valid")
+});
+
+/// What happens to the submissions past the retention period
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RetentionAction {
+    /// Deletes them altogether
+    Purge,
+    /// Keeps the budgets and the statuses for the stats, blanks out the personal data
+    #[default]
+    Anonymize,
+}
+
+/// Audit record of an erasure, it never holds the erased address itself
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Erasure {
+    pub id: i64,
+    /// Either "request" or "retention"
+    pub kind: String,
+    /// Keyed blind index of the address, so a repeated request can be matched without the
+    /// address being recoverable, only kept with `email_index_secret` set
+    pub email_index: Option<String>,
+    pub reason: Option<String>,
+    pub submissions: u64,
+    pub outbox: u64,
+    pub dead_letters: u64,
+    pub quarantine: u64,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+impl Erasure {
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            kind: row.get(1)?,
+            email_index: row.get(2)?,
+            reason: row.get(3)?,
+            submissions: row.get(4)?,
+            outbox: row.get(5)?,
+            dead_letters: row.get(6)?,
+            quarantine: row.get(7)?,
+            created_at: datetime(row.get(8)?),
+        })
+    }
+}
+
+/// Outcome of a key rotation
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rotation {
+    /// Rows re-encrypted with the active key
+    pub rotated: u64,
+    /// Rows left as they were, as they couldn't be decrypted, e.g. sealed with a dropped key
+    pub skipped: Vec<SkippedRow>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SkippedRow {
+    pub table: &'static str,
+    pub id: i64,
+}
+
+/// Rows removed per table, in the order of `PAYLOAD_TABLES`
+type Removed = [u64; 4];
+
+/// Erases the personal data on request and after the retention period
+#[derive(Clone, Debug)]
+pub struct Privacy {
+    storage: Storage,
+    cipher: FieldCipher,
+}
+
+impl Privacy {
+    pub fn new(storage: Storage, cipher: FieldCipher) -> Self {
+        Self { storage, cipher }
+    }
+
+    /// Deletes everything stored for the address and records the erasure
+    pub async fn erase(
+        &self,
+        email: &str,
+        reason: Option<String>,
+    ) -> Result<Erasure, StorageErrors> {
+        let email = email.trim().to_lowercase();
+        let email_index = self.cipher.email_index(&email);
+        let cipher = self.cipher.clone();
+
+        self.storage
+            .call(move |conn| {
+                let tx = conn.transaction()?;
+
+                // The fingerprints are hashed, so they are found through the payloads
+                let mut stmt =
+                    tx.prepare(&format!("SELECT payload FROM submissions WHERE {BY_EMAIL}"))?;
+                let fingerprints = stmt
+                    .query_map(params![email, email_index], |row| row.get::<_, String>(0))?
+                    .filter_map(|payload| {
+                        let form = cipher.open_form(&payload.ok()?).ok()?;
+                        Some(fingerprint(&form))
+                    })
+                    .collect::<Vec<_>>();
+                drop(stmt);
+                for fingerprint in fingerprints {
+                    tx.execute(
+                        "DELETE FROM submission_fingerprints WHERE fingerprint = ?1",
+                        params![fingerprint],
+                    )?;
+                }
+
+                let mut removed = Removed::default();
+                for (table, removed) in PAYLOAD_TABLES.iter().zip(removed.iter_mut()) {
+                    *removed = tx.execute(
+                        &format!("DELETE FROM {table} WHERE {BY_EMAIL}"),
+                        params![email, email_index],
+                    )? as u64;
+                }
+
+                let erasure = record(
+                    &tx,
+                    "request",
+                    email_index.as_deref(),
+                    reason.as_deref(),
+                    removed,
+                )?;
+                tx.commit()?;
+
+                Ok(erasure)
+            })
+            .await
+    }
+
+    pub async fn list(&self, limit: u32, offset: u32) -> Result<Vec<Erasure>, StorageErrors> {
+        self.storage
+            .call(move |conn| {
+                let mut stmt = conn.prepare_cached(&format!(
+                    "SELECT {ERASURE_COLUMNS} FROM erasures ORDER BY id DESC LIMIT ?1 OFFSET ?2"
+                ))?;
+                let erasures = stmt
+                    .query_map(params![limit, offset], Erasure::from_row)?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(erasures)
+            })
+            .await
+    }
+
+    /// Applies the action to the submissions older than the cutoff, and drops the dead letters
+    /// and the quarantined messages along with them
+    pub async fn apply_retention(
+        &self,
+        retention: Duration,
+        action: RetentionAction,
+    ) -> Result<Option<Erasure>, StorageErrors> {
+        let cutoff = unix_now() - retention.as_secs() as i64;
+
+        self.storage
+            .call(move |conn| {
+                let tx = conn.transaction()?;
+
+                let submissions = match action {
+                    RetentionAction::Purge => tx.execute(
+                        "DELETE FROM submissions WHERE created_at < ?1",
+                        params![cutoff],
+                    )?,
+                    RetentionAction::Anonymize => tx.execute(
+                        "UPDATE submissions
+                         SET payload = json_set(payload, '$.email', '', '$.name', '',
+                                                '$.projectDescription', ''),
+                             email_index = NULL, client_ip = NULL, origin = NULL,
+                             request_id = NULL, last_error = NULL, anonymized_at = ?2
+                         WHERE created_at < ?1 AND anonymized_at IS NULL",
+                        params![cutoff, unix_now()],
+                    )?,
+                };
+                let dead_letters = tx.execute(
+                    "DELETE FROM dead_letters WHERE failed_at < ?1",
+                    params![cutoff],
+                )?;
+                let quarantine = tx.execute(
+                    "DELETE FROM quarantine WHERE created_at < ?1",
+                    params![cutoff],
+                )?;
+                // The deliveries only point at the submissions, so they go without being counted
+                tx.execute(
+                    "DELETE FROM webhook_deliveries WHERE created_at < ?1",
+                    params![cutoff],
+                )?;
+
+                let removed = [submissions as u64, 0, dead_letters as u64, quarantine as u64];
+                if removed.iter().all(|count| *count == 0) {
+                    return Ok(None);
+                }
+
+                let erasure = record(&tx, "retention", None, None, removed)?;
+                tx.commit()?;
+
+                Ok(Some(erasure))
+            })
+            .await
+    }
+
+    /// Re-encrypts the personal fields sealed with another key than the active one, plaintext
+    /// included, in every table holding a payload, skipping the rows which can't be decrypted
+    pub async fn rotate_keys(&self) -> Result<Rotation, StorageErrors> {
+        let mut rotation = Rotation::default();
+
+        for table in PAYLOAD_TABLES {
+            let mut after_id = 0;
+
+            loop {
+                let cipher = self.cipher.clone();
+                let (last_id, count, skipped) = self
+                    .storage
+                    .call(move |conn| {
+                        let tx = conn.transaction()?;
+                        let rows = tx
+                            .prepare(&format!(
+                                "SELECT id, payload FROM {table} WHERE id > ?1 ORDER BY id LIMIT ?2"
+                            ))?
+                            .query_map(params![after_id, ROTATION_BATCH_SIZE], |row| {
+                                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+                            })?
+                            .collect::<Result<Vec<_>, _>>()?;
+
+                        let mut count = 0;
+                        let mut skipped = Vec::new();
+                        for (id, payload) in &rows {
+                            let mut payload: serde_json::Value = serde_json::from_str(payload)?;
+                            if !cipher.needs_rotation(&payload) {
+                                continue;
+                            }
+
+                            if let Err(err) = cipher.decrypt_payload(&mut payload) {
+                                tracing::warn!("{table} #{id} couldn't be decrypted: {:?}", err);
+                                skipped.push(SkippedRow { table, id: *id });
+                                continue;
+                            }
+                            let email_index = payload["email"]
+                                .as_str()
+                                .filter(|email| !email.is_empty())
+                                .and_then(|email| cipher.email_index(email));
+                            cipher.encrypt_payload(&mut payload)?;
+
+                            tx.execute(
+                                &format!(
+                                    "UPDATE {table}
+                                     SET payload = ?2, email_index = COALESCE(?3, email_index)
+                                     WHERE id = ?1"
+                                ),
+                                params![id, payload.to_string(), email_index],
+                            )?;
+                            count += 1;
+                        }
+                        tx.commit()?;
+
+                        Ok((rows.last().map(|(id, _)| *id), count, skipped))
+                    })
+                    .await?;
+
+                rotation.rotated += count;
+                rotation.skipped.extend(skipped);
+                match last_id {
+                    Some(last_id) => after_id = last_id,
+                    None => break,
+                }
+            }
+        }
+
+        Ok(rotation)
+    }
+
+    /// Runs the retention every `retention_interval` until the shutdown
+    pub fn spawn_retention(self, configs: &AppConfigs, shutdown: &Shutdown) -> JoinHandle<()> {
+        let retention = Duration::from_secs(configs.retention_days * 24 * 60 * 60);
+        let interval = Duration::from_secs(configs.retention_interval);
+        let action = configs.retention_action;
+        let stop = shutdown.clone();
+
+        shutdown.spawn(async move {
+            tracing::info!(
+                "retention task started, keeping {} days",
+                retention.as_secs() / 86400
+            );
+
+            while !stop.is_cancelled() {
+                match self.apply_retention(retention, action).await {
+                    Ok(Some(erasure)) => tracing::info!(
+                        "retention cleared {} submissions, {} dead letters, {} quarantined",
+                        erasure.submissions,
+                        erasure.dead_letters,
+                        erasure.quarantine
+                    ),
+                    Ok(None) => {}
+                    Err(err) => {
+                        tracing::error!("retention error: {:?}", err);
+                        sentry::capture_error(&err);
+                    }
+                }
+
+                tokio::select! {
+                    _ = tokio::time::sleep(interval) => {}
+                    _ = stop.cancelled() => {}
+                }
+            }
+
+            tracing::info!("retention task stopped");
+        })
+    }
+}
+
+fn record(
+    tx: &Transaction<'_>,
+    kind: &str,
+    email_index: Option<&str>,
+    reason: Option<&str>,
+    removed: Removed,
+) -> rusqlite::Result<Erasure> {
+    let [submissions, outbox, dead_letters, quarantine] = removed;
+
+    tx.execute(
+        "INSERT INTO erasures
+             (kind, email_index, reason, submissions, outbox, dead_letters, quarantine, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            kind,
+            email_index,
+            reason,
+            submissions,
+            outbox,
+            dead_letters,
+            quarantine,
+            unix_now()
+        ],
+    )?;
+
+    tx.query_row(
+        &format!("SELECT {ERASURE_COLUMNS} FROM erasures WHERE id = ?1"),
+        params![tx.last_insert_rowid()],
+        Erasure::from_row,
+    )
+}
+
+/// Masks the addresses an error may echo, e.g. a rejected recipient, before it is stored
+pub fn redact_emails(text: &str) -> Cow<'_, str> {
+    EMAIL_PATTERN.replace_all(text, "<redacted>")
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    fn cipher(keys: &[(&str, u8)]) -> FieldCipher {
+        let mut configs = AppConfigs::for_tests(&[]);
+        configs.encryption_keys = keys
+            .iter()
+            .map(|(id, byte)| format!("{id}:{}", STANDARD.encode([*byte; 32])))
+            .collect();
+        configs.email_index_secret = Some("an email index secret of 32 chars".to_string());
+
+        FieldCipher::new(&configs).unwrap()
+    }
+
+    fn payload(email: &str) -> Value {
+        json!({
+            "email": email,
+            "name": "Jane",
+            "projectDescription": "A marketing site for a small bakery, with an online order form.",
+            "minBudget": 1000,
+            "maxBudget": 2000,
+        })
+    }
+
+    fn sealed(cipher: &FieldCipher, email: &str) -> String {
+        let mut payload = payload(email);
+        cipher.encrypt_payload(&mut payload).unwrap();
+        payload.to_string()
+    }
+
+    /// Inserts a row holding the payload into any of the `PAYLOAD_TABLES`
+    async fn insert(
+        storage: &Storage,
+        table: &'static str,
+        payload: String,
+        email_index: Option<String>,
+        created_at: i64,
+    ) -> i64 {
+        storage
+            .call(move |conn| {
+                let sql = match table {
+                    "submissions" => {
+                        "INSERT INTO submissions
+                             (payload, email_index, status, request_id, client_ip, origin,
+                              last_error, created_at, updated_at)
+                         VALUES (?1, ?2, 'sent', 'req-1', '192.0.2.1', 'https://example.com',
+                                 'error for jane@example.com', ?3, ?3)"
+                    }
+                    "outbox" => {
+                        "INSERT INTO outbox
+                             (payload, email_index, next_attempt_at, created_at, updated_at)
+                         VALUES (?1, ?2, ?3, ?3, ?3)"
+                    }
+                    "dead_letters" => {
+                        "INSERT INTO dead_letters
+                             (outbox_id, payload, email_index, attempts, created_at, failed_at)
+                         VALUES (0, ?1, ?2, 3, ?3, ?3)"
+                    }
+                    "quarantine" => {
+                        "INSERT INTO quarantine (payload, email_index, score, rules, created_at)
+                         VALUES (?1, ?2, 6.0, '[]', ?3)"
+                    }
+                    _ => unreachable!(),
+                };
+                conn.execute(sql, params![payload, email_index, created_at])?;
+                Ok(conn.last_insert_rowid())
+            })
+            .await
+            .unwrap()
+    }
+
+    async fn count(storage: &Storage, sql: &'static str) -> i64 {
+        storage.call(move |conn| Ok(conn.query_row(sql, [], |row| row.get(0))?)).await.unwrap()
+    }
+
+    async fn stored(storage: &Storage, table: &'static str, id: i64) -> (String, Option<String>) {
+        storage
+            .call(move |conn| {
+                Ok(conn.query_row(
+                    &format!("SELECT payload, email_index FROM {table} WHERE id = ?1"),
+                    params![id],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )?)
+            })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn rotates_past_the_rows_sealed_with_a_dropped_key() {
+        let storage = Storage::open(":memory:").unwrap();
+        let retired = sealed(&cipher(&[("key-0", 0)]), "old@example.com");
+        let older = sealed(&cipher(&[("key-1", 1)]), "jane@example.com");
+
+        let unreadable = insert(&storage, "submissions", retired.clone(), None, unix_now()).await;
+        let rotatable = insert(&storage, "submissions", older, None, unix_now()).await;
+        let plaintext = payload("john@example.com").to_string();
+        let plaintext = insert(&storage, "quarantine", plaintext, None, unix_now()).await;
+
+        let cipher = cipher(&[("key-2", 2), ("key-1", 1)]);
+        let rotation = Privacy::new(storage.clone(), cipher.clone()).rotate_keys().await.unwrap();
+
+        assert_eq!(rotation.rotated, 2);
+        assert_eq!(rotation.skipped.len(), 1);
+        assert_eq!(rotation.skipped[0].table, "submissions");
+        assert_eq!(rotation.skipped[0].id, unreadable);
+
+        assert_eq!(stored(&storage, "submissions", unreadable).await.0, retired);
+
+        let (payload, _) = stored(&storage, "submissions", rotatable).await;
+        assert!(!cipher.needs_rotation(&serde_json::from_str(&payload).unwrap()));
+        assert_eq!(
+            cipher.open_form(&payload).unwrap().email,
+            "jane@example.com"
+        );
+
+        let (payload, email_index) = stored(&storage, "quarantine", plaintext).await;
+        assert!(!payload.contains("john@example.com"));
+        assert_eq!(email_index, cipher.email_index("john@example.com"));
+    }
+
+    #[tokio::test]
+    async fn erases_the_address_from_every_table() {
+        let storage = Storage::open(":memory:").unwrap();
+        let cipher = cipher(&[("key-1", 1)]);
+        let privacy = Privacy::new(storage.clone(), cipher.clone());
+        let jane_index = cipher.email_index("jane@example.com");
+
+        // Encrypted rows are only found through the blind index
+        for table in PAYLOAD_TABLES {
+            let payload = sealed(&cipher, "jane@example.com");
+            insert(&storage, table, payload, jane_index.clone(), unix_now()).await;
+        }
+        // Older plaintext rows are matched on the trimmed, lowercased address
+        let plaintext = payload(" Jane@Example.COM ").to_string();
+        insert(&storage, "submissions", plaintext, None, unix_now()).await;
+        let john = sealed(&cipher, "john@example.com");
+        let john_id = insert(
+            &storage,
+            "submissions",
+            john,
+            cipher.email_index("john@example.com"),
+            unix_now(),
+        )
+        .await;
+
+        let fingerprints = ["jane@example.com", "john@example.com"]
+            .map(|email| fingerprint(&serde_json::from_value(payload(email)).unwrap()));
+        for fingerprint in fingerprints.clone() {
+            storage
+                .call(move |conn| {
+                    conn.execute(
+                        "INSERT INTO submission_fingerprints (fingerprint, created_at, expires_at)
+                         VALUES (?1, ?2, ?2 + 3600)",
+                        params![fingerprint, unix_now()],
+                    )?;
+                    Ok(())
+                })
+                .await
+                .unwrap();
+        }
+
+        let erasure = privacy.erase("  JANE@example.com ", Some("ticket 42".into())).await.unwrap();
+
+        assert_eq!(erasure.kind, "request");
+        assert_eq!(erasure.email_index, jane_index);
+        assert_eq!(erasure.reason.as_deref(), Some("ticket 42"));
+        assert_eq!(
+            [erasure.submissions, erasure.outbox, erasure.dead_letters, erasure.quarantine],
+            [2, 1, 1, 1]
+        );
+
+        for sql in [
+            "SELECT COUNT(*) FROM outbox",
+            "SELECT COUNT(*) FROM dead_letters",
+            "SELECT COUNT(*) FROM quarantine",
+        ] {
+            assert_eq!(count(&storage, sql).await, 0, "{sql}");
+        }
+        assert_eq!(count(&storage, "SELECT COUNT(*) FROM submissions").await, 1);
+        assert_eq!(
+            count(&storage, "SELECT MAX(id) FROM submissions").await,
+            john_id
+        );
+
+        let remaining: Vec<String> = storage
+            .call(|conn| {
+                let mut stmt = conn.prepare("SELECT fingerprint FROM submission_fingerprints")?;
+                let rows = stmt.query_map([], |row| row.get(0))?.collect::<Result<_, _>>()?;
+                Ok(rows)
+            })
+            .await
+            .unwrap();
+        assert_eq!(remaining, vec![fingerprints[1].clone()]);
+
+        let erasures = privacy.list(10, 0).await.unwrap();
+        assert_eq!(erasures.len(), 1);
+        assert_eq!(erasures[0].id, erasure.id);
+        assert_eq!(erasures[0].email_index, jane_index);
+    }
+
+    #[tokio::test]
+    async fn erases_plaintext_rows_without_an_index_secret() {
+        let storage = Storage::open(":memory:").unwrap();
+        let mut configs = AppConfigs::for_tests(&[]);
+        configs.email_index_secret = None;
+        let privacy = Privacy::new(storage.clone(), FieldCipher::new(&configs).unwrap());
+
+        let plaintext = payload("Jane@Example.com").to_string();
+        insert(&storage, "outbox", plaintext, None, unix_now()).await;
+
+        let erasure = privacy.erase("jane@example.com", None).await.unwrap();
+
+        assert_eq!(erasure.outbox, 1);
+        assert_eq!(erasure.email_index, None);
+        assert_eq!(count(&storage, "SELECT COUNT(*) FROM outbox").await, 0);
+    }
+
+    #[tokio::test]
+    async fn anonymizes_the_expired_submissions() {
+        let storage = Storage::open(":memory:").unwrap();
+        let cipher = cipher(&[("key-1", 1)]);
+        let privacy = Privacy::new(storage.clone(), cipher.clone());
+        let expired = unix_now() - 31 * 86400;
+
+        let old = insert(
+            &storage,
+            "submissions",
+            sealed(&cipher, "jane@example.com"),
+            cipher.email_index("jane@example.com"),
+            expired,
+        )
+        .await;
+        let recent = sealed(&cipher, "john@example.com");
+        let recent = insert(&storage, "submissions", recent.clone(), None, unix_now()).await;
+        insert(
+            &storage,
+            "dead_letters",
+            sealed(&cipher, "jane@example.com"),
+            None,
+            expired,
+        )
+        .await;
+        insert(
+            &storage,
+            "dead_letters",
+            sealed(&cipher, "john@example.com"),
+            None,
+            unix_now(),
+        )
+        .await;
+        insert(
+            &storage,
+            "quarantine",
+            sealed(&cipher, "jane@example.com"),
+            None,
+            expired,
+        )
+        .await;
+
+        let retention = Duration::from_secs(30 * 86400);
+        let erasure =
+            privacy.apply_retention(retention, RetentionAction::Anonymize).await.unwrap().unwrap();
+
+        assert_eq!(erasure.kind, "retention");
+        assert_eq!(erasure.email_index, None);
+        assert_eq!(erasure.reason, None);
+        assert_eq!(
+            [erasure.submissions, erasure.outbox, erasure.dead_letters, erasure.quarantine],
+            [1, 0, 1, 1]
+        );
+
+        let (payload, email_index, metadata, anonymized_at) = storage
+            .call(move |conn| {
+                Ok(conn.query_row(
+                    "SELECT payload, email_index,
+                            COALESCE(request_id, client_ip, origin, last_error), anonymized_at
+                     FROM submissions WHERE id = ?1",
+                    params![old],
+                    |row| {
+                        Ok((
+                            row.get::<_, String>(0)?,
+                            row.get::<_, Option<String>>(1)?,
+                            row.get::<_, Option<String>>(2)?,
+                            row.get::<_, Option<i64>>(3)?,
+                        ))
+                    },
+                )?)
+            })
+            .await
+            .unwrap();
+        let payload: Value = serde_json::from_str(&payload).unwrap();
+        assert_eq!(payload["email"], "");
+        assert_eq!(payload["name"], "");
+        assert_eq!(payload["projectDescription"], "");
+        assert_eq!(payload["minBudget"], 1000);
+        assert_eq!(payload["maxBudget"], 2000);
+        assert_eq!(email_index, None);
+        assert_eq!(metadata, None);
+        assert!(anonymized_at.is_some());
+
+        let (payload, _) = stored(&storage, "submissions", recent).await;
+        assert_eq!(
+            cipher.open_form(&payload).unwrap().email,
+            "john@example.com"
+        );
+        assert_eq!(
+            count(&storage, "SELECT COUNT(*) FROM dead_letters").await,
+            1
+        );
+        assert_eq!(count(&storage, "SELECT COUNT(*) FROM quarantine").await, 0);
+
+        // The anonymized rows aren't counted again
+        assert!(
+            privacy.apply_retention(retention, RetentionAction::Anonymize).await.unwrap().is_none()
+        );
+        assert_eq!(count(&storage, "SELECT COUNT(*) FROM erasures").await, 1);
+    }
+
+    #[tokio::test]
+    async fn purges_the_expired_submissions() {
+        let storage = Storage::open(":memory:").unwrap();
+        let cipher = cipher(&[("key-1", 1)]);
+        let privacy = Privacy::new(storage.clone(), cipher.clone());
+
+        insert(
+            &storage,
+            "submissions",
+            sealed(&cipher, "jane@example.com"),
+            None,
+            0,
+        )
+        .await;
+        let recent = insert(
+            &storage,
+            "submissions",
+            sealed(&cipher, "john@example.com"),
+            None,
+            unix_now(),
+        )
+        .await;
+
+        let retention = Duration::from_secs(30 * 86400);
+        let erasure =
+            privacy.apply_retention(retention, RetentionAction::Purge).await.unwrap().unwrap();
+
+        assert_eq!(erasure.kind, "retention");
+        assert_eq!(
+            [erasure.submissions, erasure.outbox, erasure.dead_letters, erasure.quarantine],
+            [1, 0, 0, 0]
+        );
+        assert_eq!(count(&storage, "SELECT COUNT(*) FROM submissions").await, 1);
+        assert_eq!(
+            count(&storage, "SELECT MAX(id) FROM submissions").await,
+            recent
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use rusqlite::{OptionalExtension, Row, params};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use super::{
+    encryption::{FieldCipher, SealedForm},
+    notifier::{SUBMISSION_RECEIVED, WebhookQueue},
+    outbox,
+    spam::SpamVerdict,
+    storage::{Storage, datetime, unix_now},
+    submissions::{self, SubmissionOrigin, SubmissionStatus},
+};
+use crate::api::{errors::StorageErrors, models::LetsStartForm};
+
+const QUARANTINE_COLUMNS: &str = "id, payload, score, rules, created_at, released_at";
+
+/// Submission the spam filter held back for a human to look at
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Quarantined {
+    pub id: i64,
+    pub payload: serde_json::Value,
+    pub score: f64,
+    pub rules: Vec<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub released_at: Option<OffsetDateTime>,
+}
+
+impl Quarantined {
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        let payload: String = row.get(1)?;
+        let rules: String = row.get(3)?;
+
+        Ok(Self {
+            id: row.get(0)?,
+            payload: serde_json::from_str(&payload).unwrap_or(serde_json::Value::String(payload)),
+            score: row.get(2)?,
+            rules: rules.split(',').filter(|rule| !rule.is_empty()).map(str::to_string).collect(),
+            created_at: datetime(row.get(4)?),
+            released_at: row.get::<_, Option<i64>>(5)?.map(datetime),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Quarantine {
+    storage: Storage,
+    cipher: FieldCipher,
+    webhooks: WebhookQueue,
+}
+
+impl Quarantine {
+    pub fn new(storage: Storage, cipher: FieldCipher, webhooks: WebhookQueue) -> Self {
+        Self { storage, cipher, webhooks }
+    }
+
+    /// Stores the submission as quarantined along with the verdict, returns the quarantine id
+    pub async fn put(
+        &self,
+        form: &LetsStartForm,
+        verdict: &SpamVerdict,
+        origin: SubmissionOrigin,
+    ) -> Result<i64, StorageErrors> {
+        let form = self.cipher.seal_form(form)?;
+        let score = verdict.score;
+        let rules = verdict.rules.join(",");
+
+        self.storage
+            .call(move |conn| {
+                let tx = conn.transaction()?;
+                let submission_id =
+                    submissions::insert(&tx, &form, &origin, SubmissionStatus::Quarantined)?;
+                tx.execute(
+                    "INSERT INTO quarantine
+                         (payload, email_index, score, rules, submission_id, created_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![
+                        form.payload,
+                        form.email_index,
+                        score,
+                        rules,
+                        submission_id,
+                        unix_now()
+                    ],
+                )?;
+                let id = tx.last_insert_rowid();
+                tx.commit()?;
+                Ok(id)
+            })
+            .await
+    }
+
+    pub async fn list(&self, limit: u32, offset: u32) -> Result<Vec<Quarantined>, StorageErrors> {
+        let mut quarantined = self
+            .storage
+            .call(move |conn| {
+                let mut stmt = conn.prepare_cached(&format!(
+                    "SELECT {QUARANTINE_COLUMNS} FROM quarantine
+                     ORDER BY id DESC LIMIT ?1 OFFSET ?2"
+                ))?;
+                let quarantined = stmt
+                    .query_map(params![limit, offset], Quarantined::from_row)?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(quarantined)
+            })
+            .await?;
+        self.decrypt(&mut quarantined);
+
+        Ok(quarantined)
+    }
+
+    pub async fn get(&self, id: i64) -> Result<Option<Quarantined>, StorageErrors> {
+        let mut quarantined = self
+            .storage
+            .call(move |conn| {
+                let quarantined = conn
+                    .query_row(
+                        &format!("SELECT {QUARANTINE_COLUMNS} FROM quarantine WHERE id = ?1"),
+                        params![id],
+                        Quarantined::from_row,
+                    )
+                    .optional()?;
+                Ok(quarantined)
+            })
+            .await?;
+        self.decrypt(quarantined.iter_mut());
+
+        Ok(quarantined)
+    }
+
+    /// Lets a submission through to the outbox once, returns its ticket
+    pub async fn release(&self, id: i64) -> Result<Option<i64>, StorageErrors> {
+        let webhooks = self.webhooks.clone();
+
+        self.storage
+            .call(move |conn| {
+                let tx = conn.transaction()?;
+
+                let quarantined = tx
+                    .query_row(
+                        "SELECT payload, email_index, submission_id FROM quarantine
+                         WHERE id = ?1 AND released_at IS NULL",
+                        params![id],
+                        |row| {
+                            let form =
+                                SealedForm { payload: row.get(0)?, email_index: row.get(1)? };
+                            Ok((form, row.get::<_, Option<i64>>(2)?))
+                        },
+                    )
+                    .optional()?;
+                let Some((form, submission_id)) = quarantined else {
+                    return Ok(None);
+                };
+
+                let ticket = outbox::insert(&tx, &form, None)?;
+                tx.execute(
+                    "UPDATE submissions SET ticket = ?1 WHERE id = ?2",
+                    params![ticket, submission_id],
+                )?;
+                submissions::set_status(&tx, ticket, SubmissionStatus::Received, None)?;
+                // The held back submissions were never announced, so they are only now received
+                if let Some(submission_id) = submission_id {
+                    webhooks.insert(&tx, submission_id, SUBMISSION_RECEIVED)?;
+                }
+
+                tx.execute(
+                    "UPDATE quarantine SET released_at = ?2 WHERE id = ?1",
+                    params![id, unix_now()],
+                )?;
+                tx.commit()?;
+
+                Ok(Some(ticket))
+            })
+            .await
+    }
+
+    fn decrypt<'a>(&self, quarantined: impl IntoIterator<Item = &'a mut Quarantined>) {
+        for quarantined in quarantined {
+            self.cipher.open_payload(
+                &mut quarantined.payload,
+                format_args!("quarantined #{}", quarantined.id),
+            );
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use axum::http::HeaderMap;
+use ipnet::{IpNet, Ipv6Net};
+use tokio::task::JoinHandle;
+
+use crate::shutdown::Shutdown;
+
+/// How often the refilled buckets are dropped, so the requests never pay for it
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// An IPv6 client usually gets the whole /64, so it is limited as one
+const IPV6_PREFIX_LEN: u8 = 64;
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Token bucket per key: `burst` tokens at most, one more every `refill`
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    burst: f64,
+    refill: Duration,
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(burst: u32, refill_secs: u64) -> Self {
+        Self {
+            burst: f64::from(burst),
+            refill: Duration::from_secs(refill_secs),
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Takes a token for the key, or tells how long to wait for the next one
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let bucket = buckets
+            .entry(key.to_string())
+            .or_insert(Bucket { tokens: self.burst, updated_at: now });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        Err(self.refill.mul_f64(1.0 - bucket.tokens))
+    }
+
+    /// Drops the buckets, which refilled up to the burst, until the shutdown
+    pub fn spawn_pruner(self, shutdown: &Shutdown) -> JoinHandle<()> {
+        let shutdown = shutdown.clone();
+
+        shutdown.clone().spawn(async move {
+            let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => self.prune(),
+                    _ = shutdown.cancelled() => break,
+                }
+            }
+        })
+    }
+
+    fn prune(&self) {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let tracked = buckets.len();
+        buckets.retain(|_, bucket| self.refilled(bucket, now) < self.burst);
+        tracing::debug!(
+            "rate limiter pruned {} of {} buckets",
+            tracked - buckets.len(),
+            tracked
+        );
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        (bucket.tokens + elapsed / self.refill.as_secs_f64()).min(self.burst)
+    }
+}
+
+/// Proxies whose `X-Forwarded-For` header is trusted to name the client
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies {
+    nets: Arc<Vec<IpNet>>,
+}
+
+impl TrustedProxies {
+    pub fn new(proxies: &[String]) -> anyhow::Result<Self> {
+        let nets = proxies
+            .iter()
+            .map(|proxy| {
+                parse_net(proxy).with_context(|| format!("invalid trusted proxy <{proxy}>"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Self { nets: Arc::new(nets) })
+    }
+
+    /// Walks the `X-Forwarded-For` chain from the peer back to the first untrusted hop
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let peer = peer.to_canonical();
+        if !self.is_trusted(peer) {
+            return peer;
+        }
+
+        let hops = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|hop| hop.trim().parse::<IpAddr>())
+            .collect::<Vec<_>>();
+
+        let mut client = peer;
+        for hop in hops.into_iter().rev() {
+            // A garbled hop can't be vouched for, so the last trusted one answers for it
+            let Ok(hop) = hop.map(|hop| hop.to_canonical()) else {
+                break;
+            };
+            client = hop;
+            if !self.is_trusted(hop) {
+                break;
+            }
+        }
+        client
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.nets.iter().any(|net| net.contains(&ip))
+    }
+}
+
+/// Rate limit key of the client, its /64 for IPv6
+pub fn ip_key(ip: IpAddr) -> String {
+    match ip.to_canonical() {
+        IpAddr::V6(ip) => Ipv6Net::new(ip, IPV6_PREFIX_LEN)
+            .map_or_else(|_| ip.to_string(), |net| net.trunc().to_string()),
+        ip => ip.to_string(),
+    }
+}
+
+pub fn parse_net(value: &str) -> anyhow::Result<IpNet> {
+    let value = value.trim();
+    if let Ok(ip) = IpAddr::from_str(value) {
+        return Ok(IpNet::from(ip));
+    }
+
+    Ok(IpNet::from_str(value)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_an_ipv6_client_by_its_prefix() {
+        let key = |ip: &str| ip_key(ip.parse().unwrap());
+
+        assert_eq!(key("2001:db8:1:2:aaaa::1"), "2001:db8:1:2::/64");
+        assert_eq!(key("2001:db8:1:2:bbbb::2"), key("2001:db8:1:2:aaaa::1"));
+        assert_ne!(key("2001:db8:1:3::1"), key("2001:db8:1:2::1"));
+        assert_eq!(key("::ffff:192.0.2.1"), "192.0.2.1");
+        assert_eq!(key("192.0.2.1"), "192.0.2.1");
+    }
+
+    #[test]
+    fn prunes_only_the_refilled_buckets() {
+        let limiter = RateLimiter::new(2, 3600);
+        limiter.check("busy").unwrap();
+        limiter.buckets.lock().unwrap().insert(
+            "idle".to_string(),
+            Bucket { tokens: 2.0, updated_at: Instant::now() },
+        );
+
+        limiter.prune();
+
+        let buckets = limiter.buckets.lock().unwrap();
+        assert!(buckets.contains_key("busy"));
+        assert!(!buckets.contains_key("idle"));
+    }
+}
@@ -0,0 +1,123 @@
+use std::str::FromStr;
+
+use anyhow::Context;
+use lettre::message::Mailbox;
+
+use crate::{
+    api::models::LetsStartForm,
+    configs::{AppConfigs, RoutingRule},
+};
+
+/// Mailboxes a single lead email is addressed to
+#[derive(Clone, Debug, Default)]
+pub struct Recipients {
+    pub to: Vec<Mailbox>,
+    pub cc: Vec<Mailbox>,
+    pub bcc: Vec<Mailbox>,
+}
+
+impl Recipients {
+    fn parse(to: &[String], cc: &[String], bcc: &[String]) -> anyhow::Result<Self> {
+        Ok(Self { to: parse_mailboxes(to)?, cc: parse_mailboxes(cc)?, bcc: parse_mailboxes(bcc)? })
+    }
+
+    fn extend(&mut self, other: &Recipients) {
+        self.to.extend(other.to.iter().cloned());
+        self.cc.extend(other.cc.iter().cloned());
+        self.bcc.extend(other.bcc.iter().cloned());
+    }
+
+    /// Keeps a single copy of every address, preferring `to` over `cc` over `bcc`
+    fn dedup(mut self) -> Self {
+        let mut seen = Vec::new();
+        for list in [&mut self.to, &mut self.cc, &mut self.bcc] {
+            list.retain(|mailbox| {
+                let address = mailbox.email.to_string().to_ascii_lowercase();
+                if seen.contains(&address) {
+                    return false;
+                }
+                seen.push(address);
+                true
+            });
+        }
+        self
+    }
+}
+
+#[derive(Clone, Debug)]
+struct CompiledRule {
+    name: String,
+    min_budget_at_least: Option<u16>,
+    max_budget_at_least: Option<u16>,
+    keywords: Vec<String>,
+    recipients: Recipients,
+}
+
+impl CompiledRule {
+    fn matches(&self, form: &LetsStartForm) -> bool {
+        let description = form.project_description.to_lowercase();
+
+        self.min_budget_at_least.is_none_or(|min| form.min_budget >= min)
+            && self.max_budget_at_least.is_none_or(|min| form.max_budget >= min)
+            && (self.keywords.is_empty()
+                || self.keywords.iter().any(|keyword| description.contains(keyword.as_str())))
+    }
+}
+
+/// Picks the recipients of a lead email from the defaults and the matching routing rules
+#[derive(Clone, Debug)]
+pub struct Routing {
+    defaults: Recipients,
+    rules: Vec<CompiledRule>,
+}
+
+impl Routing {
+    pub fn new(configs: &AppConfigs) -> anyhow::Result<Self> {
+        let defaults = Recipients::parse(
+            &configs.to_mailboxes,
+            &configs.cc_mailboxes,
+            &configs.bcc_mailboxes,
+        )
+        .context("invalid or incompatible default recipients")?;
+
+        let rules =
+            configs.routing_rules.iter().map(compile_rule).collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Self { defaults, rules })
+    }
+
+    pub fn recipients(&self, form: &LetsStartForm) -> Recipients {
+        let mut recipients = self.defaults.clone();
+
+        for rule in self.rules.iter().filter(|rule| rule.matches(form)) {
+            tracing::debug!("routing rule <{}> matched", rule.name);
+            recipients.extend(&rule.recipients);
+        }
+
+        recipients.dedup()
+    }
+}
+
+fn compile_rule(rule: &RoutingRule) -> anyhow::Result<CompiledRule> {
+    let recipients = Recipients::parse(&rule.to, &rule.cc, &rule.bcc)
+        .with_context(|| format!("invalid or incompatible recipients of <{}>", rule.name))?;
+
+    Ok(CompiledRule {
+        name: rule.name.clone(),
+        min_budget_at_least: rule.min_budget_at_least,
+        max_budget_at_least: rule.max_budget_at_least,
+        keywords: rule.keywords.iter().map(|keyword| keyword.to_lowercase()).collect(),
+        recipients,
+    })
+}
+
+fn parse_mailboxes(mailboxes: &[String]) -> anyhow::Result<Vec<Mailbox>> {
+    mailboxes
+        .iter()
+        .map(|mailbox| {
+            Mailbox::from_str(mailbox)
+                .inspect_err(|err| tracing::error!("mailbox error: {:?}", err))
+                .with_context(|| format!("invalid or incompatible mailbox <{mailbox}>"))
+        })
+        .collect()
+}
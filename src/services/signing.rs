@@ -0,0 +1,20 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// HMAC-SHA256 of the message under the key
+pub fn hmac_sha256(key: &[u8], msg: &[u8]) -> [u8; 32] {
+    mac(key, msg).finalize().into_bytes().into()
+}
+
+/// Checks the tag of the message in constant time
+pub fn verify_hmac_sha256(key: &[u8], msg: &[u8], tag: &[u8]) -> bool {
+    mac(key, msg).verify_slice(tag).is_ok()
+}
+
+fn mac(key: &[u8], msg: &[u8]) -> HmacSha256 {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC takes a key of any length");
+    mac.update(msg);
+    mac
+}
@@ -0,0 +1,241 @@
+use std::{fmt::Debug, sync::Arc};
+
+use regex::Regex;
+
+use super::domain_policy::{DomainPolicy, DomainVerdict};
+use crate::{api::models::LetsStartForm, configs::AppConfigs};
+
+/// What happens to a submission, ordered from the mildest
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SpamAction {
+    Accept,
+    Tag,
+    Quarantine,
+    Reject,
+}
+
+#[derive(Clone, Debug)]
+pub struct SpamVerdict {
+    pub score: f64,
+    pub rules: Vec<&'static str>,
+    pub action: SpamAction,
+}
+
+/// A single heuristic, scoring zero when the form looks fine to it
+pub trait SpamRule: Debug + Send + Sync {
+    fn name(&self) -> &'static str;
+    fn score(&self, form: &LetsStartForm) -> f64;
+}
+
+/// Scores the links in the description past the allowed number
+#[derive(Debug)]
+pub struct LinkCountRule {
+    pattern: Regex,
+    max_links: usize,
+    points: f64,
+}
+
+impl SpamRule for LinkCountRule {
+    fn name(&self) -> &'static str {
+        "links"
+    }
+
+    fn score(&self, form: &LetsStartForm) -> f64 {
+        let links = self.pattern.find_iter(&form.project_description).count();
+        links.saturating_sub(self.max_links) as f64 * self.points
+    }
+}
+
+/// Scores every blocked keyword found in the name or the description
+#[derive(Debug)]
+pub struct KeywordRule {
+    keywords: Vec<String>,
+    points: f64,
+}
+
+impl SpamRule for KeywordRule {
+    fn name(&self) -> &'static str {
+        "keywords"
+    }
+
+    fn score(&self, form: &LetsStartForm) -> f64 {
+        let text = format!("{} {}", form.name, form.project_description).to_lowercase();
+        let hits = self.keywords.iter().filter(|keyword| text.contains(keyword.as_str())).count();
+        hits as f64 * self.points
+    }
+}
+
+/// Scores the addresses of the disposable providers, unless the domain is allowlisted
+#[derive(Debug)]
+pub struct DisposableDomainRule {
+    policy: DomainPolicy,
+    points: f64,
+}
+
+impl SpamRule for DisposableDomainRule {
+    fn name(&self) -> &'static str {
+        "disposable_domain"
+    }
+
+    fn score(&self, form: &LetsStartForm) -> f64 {
+        let is_disposable = self.policy.check(&form.email) == DomainVerdict::Disposable;
+        if is_disposable { self.points } else { 0.0 }
+    }
+}
+
+/// Scores runs of the same character like "!!!!!!!!" or "aaaaaaaa"
+#[derive(Debug)]
+pub struct RepeatedCharsRule {
+    max_run: usize,
+    points: f64,
+}
+
+impl SpamRule for RepeatedCharsRule {
+    fn name(&self) -> &'static str {
+        "repeated_chars"
+    }
+
+    fn score(&self, form: &LetsStartForm) -> f64 {
+        let exceeds = [&form.name, &form.project_description]
+            .iter()
+            .any(|text| longest_run(text) > self.max_run);
+        if exceeds { self.points } else { 0.0 }
+    }
+}
+
+fn longest_run(text: &str) -> usize {
+    let mut longest = 0;
+    let mut run = 0;
+    let mut previous = None;
+
+    for char in text.chars().filter(|char| !char.is_whitespace()) {
+        run = if previous == Some(char) { run + 1 } else { 1 };
+        longest = longest.max(run);
+        previous = Some(char);
+    }
+    longest
+}
+
+/// Sums the scores of all the rules and maps the total onto an action
+#[derive(Clone, Debug)]
+pub struct SpamFilter {
+    rules: Arc<Vec<Box<dyn SpamRule>>>,
+    tag_score: f64,
+    quarantine_score: f64,
+    reject_score: f64,
+}
+
+impl SpamFilter {
+    pub fn new(configs: &AppConfigs) -> anyhow::Result<Self> {
+        let rules: Vec<Box<dyn SpamRule>> = vec![
+            Box::new(LinkCountRule {
+                pattern: Regex::new(r"(?i)\b(?:https?://|www\.)")?,
+                max_links: configs.spam_max_links,
+                points: configs.spam_link_score,
+            }),
+            Box::new(KeywordRule {
+                keywords: configs
+                    .spam_keywords
+                    .iter()
+                    .map(|keyword| keyword.to_lowercase())
+                    .collect(),
+                points: configs.spam_keyword_score,
+            }),
+            Box::new(DisposableDomainRule {
+                policy: DomainPolicy::scoring(configs)?,
+                points: configs.spam_disposable_score,
+            }),
+            Box::new(RepeatedCharsRule {
+                max_run: configs.spam_max_repeated_chars,
+                points: configs.spam_repeated_chars_score,
+            }),
+        ];
+
+        Ok(Self {
+            rules: Arc::new(rules),
+            tag_score: configs.spam_tag_score,
+            quarantine_score: configs.spam_quarantine_score,
+            reject_score: configs.spam_reject_score,
+        })
+    }
+
+    pub fn verdict(&self, form: &LetsStartForm) -> SpamVerdict {
+        let mut score = 0.0;
+        let mut rules = Vec::new();
+
+        for rule in self.rules.iter() {
+            let points = rule.score(form);
+            if points > 0.0 {
+                score += points;
+                rules.push(rule.name());
+            }
+        }
+
+        let action = match score {
+            score if score >= self.reject_score => SpamAction::Reject,
+            score if score >= self.quarantine_score => SpamAction::Quarantine,
+            score if score >= self.tag_score => SpamAction::Tag,
+            _ => SpamAction::Accept,
+        };
+
+        SpamVerdict { score, rules, action }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn form(email: &str) -> LetsStartForm {
+        serde_json::from_value(serde_json::json!({
+            "email": email,
+            "minBudget": 1000,
+            "maxBudget": 2000,
+            "name": "Jane",
+            "projectDescription": "A marketing site for a small bakery, with an online order form.",
+        }))
+        .expect("the form deserializes")
+    }
+
+    #[test]
+    fn scores_disposable_domains() {
+        let filter = SpamFilter::new(&AppConfigs::for_tests(&[])).unwrap();
+
+        let verdict = filter.verdict(&form("jane@mailinator.com"));
+        assert_eq!(verdict.rules, vec!["disposable_domain"]);
+        assert_eq!(verdict.action, SpamAction::Tag);
+
+        let verdict = filter.verdict(&form("jane@sub.mailinator.com"));
+        assert_eq!(verdict.rules, vec!["disposable_domain"]);
+
+        let verdict = filter.verdict(&form("jane@example.com"));
+        assert!(verdict.rules.is_empty());
+        assert_eq!(verdict.action, SpamAction::Accept);
+    }
+
+    #[test]
+    fn skips_allowlisted_disposable_domains() {
+        let mut configs = AppConfigs::for_tests(&[]);
+        configs.email_domain_allowlist = vec!["mailinator.com".to_string()];
+        let filter = SpamFilter::new(&configs).unwrap();
+
+        let verdict = filter.verdict(&form("jane@mailinator.com"));
+        assert!(verdict.rules.is_empty());
+        assert_eq!(verdict.action, SpamAction::Accept);
+
+        let verdict = filter.verdict(&form("jane@yopmail.com"));
+        assert_eq!(verdict.rules, vec!["disposable_domain"]);
+    }
+
+    #[test]
+    fn scores_disposable_domains_when_not_blocked() {
+        let filter = SpamFilter::new(&AppConfigs::for_tests(&[(
+            "block_disposable_domains",
+            "false",
+        )]))
+        .unwrap();
+
+        let verdict = filter.verdict(&form("jane@mailinator.com"));
+        assert_eq!(verdict.rules, vec!["disposable_domain"]);
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use rusqlite::Connection;
+use time::OffsetDateTime;
+
+use crate::api::errors::StorageErrors;
+
+/// Schema migrations, applied in order and tracked by the `user_version` pragma
+const MIGRATIONS: &[&str] = &[
+    // 1: outbox
+    r#"
+    CREATE TABLE outbox (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        payload TEXT NOT NULL,
+        status TEXT NOT NULL DEFAULT 'pending',
+        attempts INTEGER NOT NULL DEFAULT 0,
+        last_error TEXT,
+        next_attempt_at INTEGER NOT NULL,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+    CREATE INDEX outbox_due_idx ON outbox (status, next_attempt_at);
+    "#,
+    // 2: dead letters
+    r#"
+    CREATE TABLE dead_letters (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        outbox_id INTEGER NOT NULL,
+        payload TEXT NOT NULL,
+        last_error TEXT,
+        attempts INTEGER NOT NULL,
+        created_at INTEGER NOT NULL,
+        failed_at INTEGER NOT NULL,
+        replayed_at INTEGER,
+        replay_count INTEGER NOT NULL DEFAULT 0
+    );
+    INSERT INTO dead_letters (outbox_id, payload, last_error, attempts, created_at, failed_at)
+        SELECT id, payload, last_error, attempts, created_at, updated_at
+        FROM outbox WHERE status = 'failed';
+    DELETE FROM outbox WHERE status = 'failed';
+    "#,
+    // 3: ticket numbers
+    r#"
+    ALTER TABLE outbox ADD COLUMN ticket INTEGER;
+    UPDATE outbox SET ticket = id;
+    ALTER TABLE dead_letters ADD COLUMN ticket INTEGER;
+    UPDATE dead_letters SET ticket = outbox_id;
+    "#,
+    // 4: quarantine
+    r#"
+    CREATE TABLE quarantine (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        submission_id INTEGER,
+        payload TEXT NOT NULL,
+        email_index TEXT,
+        score REAL NOT NULL,
+        rules TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        released_at INTEGER
+    );
+    CREATE INDEX quarantine_email_index_idx ON quarantine (email_index);
+    "#,
+    // 5: idempotency keys, scoped by the route
+    r#"
+    CREATE TABLE idempotency_keys (
+        scope TEXT NOT NULL,
+        key TEXT NOT NULL,
+        fingerprint TEXT NOT NULL,
+        status INTEGER,
+        content_type TEXT,
+        body TEXT,
+        created_at INTEGER NOT NULL,
+        expires_at INTEGER NOT NULL,
+        PRIMARY KEY (scope, key)
+    );
+    CREATE INDEX idempotency_keys_expires_idx ON idempotency_keys (expires_at);
+    "#,
+    // 6: submission fingerprints
+    r#"
+    CREATE TABLE submission_fingerprints (
+        fingerprint TEXT PRIMARY KEY,
+        created_at INTEGER NOT NULL,
+        expires_at INTEGER NOT NULL
+    );
+    CREATE INDEX submission_fingerprints_expires_idx ON submission_fingerprints (expires_at);
+    "#,
+    // 7: submissions
+    r#"
+    CREATE TABLE submissions (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        ticket INTEGER,
+        payload TEXT NOT NULL,
+        email_index TEXT,
+        status TEXT NOT NULL,
+        request_id TEXT,
+        client_ip TEXT,
+        origin TEXT,
+        last_error TEXT,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL,
+        sent_at INTEGER
+    );
+    CREATE INDEX submissions_ticket_idx ON submissions (ticket);
+    CREATE INDEX submissions_status_idx ON submissions (status, created_at);
+    CREATE INDEX submissions_email_index_idx ON submissions (email_index);
+    "#,
+    // 8: erasures, the addresses are kept as their keyed index only
+    r#"
+    CREATE TABLE erasures (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        kind TEXT NOT NULL,
+        email_index TEXT,
+        reason TEXT,
+        submissions INTEGER NOT NULL,
+        outbox INTEGER NOT NULL,
+        dead_letters INTEGER NOT NULL,
+        quarantine INTEGER NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+    ALTER TABLE submissions ADD COLUMN anonymized_at INTEGER;
+    CREATE INDEX submissions_created_idx ON submissions (created_at);
+    "#,
+    // 9: blind index of the encrypted addresses on the delivery tables
+    r#"
+    ALTER TABLE outbox ADD COLUMN email_index TEXT;
+    CREATE INDEX outbox_email_index_idx ON outbox (email_index);
+    ALTER TABLE dead_letters ADD COLUMN email_index TEXT;
+    CREATE INDEX dead_letters_email_index_idx ON dead_letters (email_index);
+    "#,
+    // 10: webhook deliveries, scheduled in msec as the webhook retries are sub-second
+    r#"
+    CREATE TABLE webhook_deliveries (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        webhook TEXT NOT NULL,
+        submission_id INTEGER NOT NULL,
+        event TEXT NOT NULL,
+        status TEXT NOT NULL DEFAULT 'pending',
+        attempts INTEGER NOT NULL DEFAULT 0,
+        last_error TEXT,
+        next_attempt_at_ms INTEGER NOT NULL,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+    CREATE INDEX webhook_deliveries_due_idx ON webhook_deliveries (status, next_attempt_at_ms);
+    "#,
+];
+
+#[derive(Clone, Debug)]
+pub struct Storage {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl Storage {
+    pub fn open(path: &str) -> anyhow::Result<Self> {
+        if let Some(dir) = Path::new(path).parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .inspect_err(|err| tracing::error!("storage dir error: {:?}", err))
+                .context("couldn't create the storage directory")?;
+        }
+
+        let mut conn = Connection::open(path)
+            .inspect_err(|err| tracing::error!("storage error: {:?}", err))
+            .context("couldn't open the storage")?;
+
+        conn.pragma_update(None, "journal_mode", "WAL")
+            .context("couldn't switch the storage to WAL mode")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")
+            .context("couldn't set the storage synchronous mode")?;
+
+        migrate(&mut conn).context("couldn't migrate the storage")?;
+
+        Ok(Self { conn: Arc::new(Mutex::new(conn)) })
+    }
+
+    /// Runs a closure against the connection on the blocking thread pool
+    pub async fn call<F, T>(&self, f: F) -> Result<T, StorageErrors>
+    where
+        F: FnOnce(&mut Connection) -> Result<T, StorageErrors> + Send + 'static,
+        T: Send + 'static,
+    {
+        let conn = Arc::clone(&self.conn);
+
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().map_err(|_| StorageErrors::PoisonError)?;
+            f(&mut conn)
+        })
+        .await?
+    }
+}
+
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+
+    for (idx, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", idx + 1)?;
+        tx.commit()?;
+
+        tracing::info!("storage migrated to version {}", idx + 1);
+    }
+
+    Ok(())
+}
+
+pub fn unix_now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or_default()
+}
+
+pub fn unix_now_millis() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as i64).unwrap_or_default()
+}
+
+pub fn datetime(timestamp: i64) -> OffsetDateTime {
+    OffsetDateTime::from_unix_timestamp(timestamp).unwrap_or(OffsetDateTime::UNIX_EPOCH)
+}
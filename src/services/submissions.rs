@@ -0,0 +1,306 @@
+use std::net::IpAddr;
+
+use rusqlite::{OptionalExtension, Row, params, params_from_iter, types::Value};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use super::{
+    encryption::{FieldCipher, SealedForm},
+    storage::{Storage, datetime, unix_now},
+};
+use crate::api::errors::StorageErrors;
+
+const SUBMISSION_COLUMNS: &str = "id, ticket, payload, status, request_id, client_ip, origin, \
+                                  last_error, created_at, updated_at, sent_at";
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SubmissionStatus {
+    Received,
+    /// Held back by the spam filter until released
+    Quarantined,
+    Sent,
+    Failed,
+}
+
+impl SubmissionStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Received => "received",
+            Self::Quarantined => "quarantined",
+            Self::Sent => "sent",
+            Self::Failed => "failed",
+        }
+    }
+
+    fn parse(status: &str) -> Self {
+        match status {
+            "quarantined" => Self::Quarantined,
+            "sent" => Self::Sent,
+            "failed" => Self::Failed,
+            _ => Self::Received,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Submission {
+    pub id: i64,
+    pub ticket: Option<i64>,
+    pub payload: serde_json::Value,
+    pub status: SubmissionStatus,
+    pub request_id: Option<String>,
+    pub client_ip: Option<String>,
+    pub origin: Option<String>,
+    pub last_error: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub updated_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub sent_at: Option<OffsetDateTime>,
+}
+
+impl Submission {
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        let payload: String = row.get(2)?;
+        let status: String = row.get(3)?;
+
+        Ok(Self {
+            id: row.get(0)?,
+            ticket: row.get(1)?,
+            payload: serde_json::from_str(&payload).unwrap_or(serde_json::Value::String(payload)),
+            status: SubmissionStatus::parse(&status),
+            request_id: row.get(4)?,
+            client_ip: row.get(5)?,
+            origin: row.get(6)?,
+            last_error: row.get(7)?,
+            created_at: datetime(row.get(8)?),
+            updated_at: datetime(row.get(9)?),
+            sent_at: row.get::<_, Option<i64>>(10)?.map(datetime),
+        })
+    }
+}
+
+/// Narrows the submissions down, every condition set must hold
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubmissionFilter {
+    pub status: Option<SubmissionStatus>,
+    /// Received at or after
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub from: Option<OffsetDateTime>,
+    /// Received before
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub to: Option<OffsetDateTime>,
+    /// Budget ranges reaching up to at least this much
+    pub min_budget: Option<u16>,
+    /// Budget ranges starting at this much at most
+    pub max_budget: Option<u16>,
+    /// Either the `Origin` header or the client IP
+    pub origin: Option<String>,
+}
+
+impl SubmissionFilter {
+    fn where_clause(&self) -> (String, Vec<Value>) {
+        let mut conditions = vec!["1 = 1".to_string()];
+        let mut values = Vec::new();
+
+        if let Some(status) = self.status {
+            values.push(Value::Text(status.as_str().to_string()));
+            conditions.push(format!("status = ?{}", values.len()));
+        }
+        if let Some(from) = self.from {
+            values.push(Value::Integer(from.unix_timestamp()));
+            conditions.push(format!("created_at >= ?{}", values.len()));
+        }
+        if let Some(to) = self.to {
+            values.push(Value::Integer(to.unix_timestamp()));
+            conditions.push(format!("created_at < ?{}", values.len()));
+        }
+        if let Some(min_budget) = self.min_budget {
+            values.push(Value::Integer(min_budget.into()));
+            conditions.push(format!(
+                "json_extract(payload, '$.maxBudget') >= ?{}",
+                values.len()
+            ));
+        }
+        if let Some(max_budget) = self.max_budget {
+            values.push(Value::Integer(max_budget.into()));
+            conditions.push(format!(
+                "json_extract(payload, '$.minBudget') <= ?{}",
+                values.len()
+            ));
+        }
+        if let Some(origin) = &self.origin {
+            values.push(Value::Text(origin.clone()));
+            conditions.push(format!("(origin = ?{0} OR client_ip = ?{0})", values.len()));
+        }
+
+        (conditions.join(" AND "), values)
+    }
+}
+
+/// Where a submission came from, kept for the audit
+#[derive(Clone, Debug)]
+pub struct SubmissionOrigin {
+    pub request_id: Option<String>,
+    pub client_ip: IpAddr,
+    pub origin: Option<String>,
+}
+
+/// Record of every lead that made it past the checks, independent of the mail delivery
+#[derive(Clone, Debug)]
+pub struct Submissions {
+    storage: Storage,
+    cipher: FieldCipher,
+}
+
+impl Submissions {
+    pub fn new(storage: Storage, cipher: FieldCipher) -> Self {
+        Self { storage, cipher }
+    }
+
+    /// Returns a page of the matching submissions, newest first, along with their total
+    pub async fn list(
+        &self,
+        filter: SubmissionFilter,
+        limit: u32,
+        offset: u32,
+    ) -> Result<(Vec<Submission>, u64), StorageErrors> {
+        let (mut submissions, total) = self
+            .storage
+            .call(move |conn| {
+                let (where_clause, mut values) = filter.where_clause();
+
+                let total = conn.query_row(
+                    &format!("SELECT COUNT(*) FROM submissions WHERE {where_clause}"),
+                    params_from_iter(values.iter()),
+                    |row| row.get::<_, u64>(0),
+                )?;
+
+                values.push(Value::Integer(limit.into()));
+                values.push(Value::Integer(offset.into()));
+                let mut stmt = conn.prepare(&format!(
+                    "SELECT {SUBMISSION_COLUMNS} FROM submissions WHERE {where_clause}
+                     ORDER BY id DESC LIMIT ?{} OFFSET ?{}",
+                    values.len() - 1,
+                    values.len()
+                ))?;
+                let submissions = stmt
+                    .query_map(params_from_iter(values.iter()), Submission::from_row)?
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Ok((submissions, total))
+            })
+            .await?;
+        self.decrypt(&mut submissions);
+
+        Ok((submissions, total))
+    }
+
+    /// Returns the next matching submissions past the given id, oldest first
+    pub async fn batch_after(
+        &self,
+        filter: &SubmissionFilter,
+        after_id: i64,
+        limit: u32,
+    ) -> Result<Vec<Submission>, StorageErrors> {
+        let (where_clause, mut values) = filter.where_clause();
+
+        let mut submissions = self
+            .storage
+            .call(move |conn| {
+                values.push(Value::Integer(after_id));
+                values.push(Value::Integer(limit.into()));
+                let mut stmt = conn.prepare(&format!(
+                    "SELECT {SUBMISSION_COLUMNS} FROM submissions
+                     WHERE {where_clause} AND id > ?{} ORDER BY id LIMIT ?{}",
+                    values.len() - 1,
+                    values.len()
+                ))?;
+                let submissions = stmt
+                    .query_map(params_from_iter(values.iter()), Submission::from_row)?
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Ok(submissions)
+            })
+            .await?;
+        self.decrypt(&mut submissions);
+
+        Ok(submissions)
+    }
+
+    pub async fn get(&self, id: i64) -> Result<Option<Submission>, StorageErrors> {
+        let mut submission = self
+            .storage
+            .call(move |conn| {
+                let submission = conn
+                    .query_row(
+                        &format!("SELECT {SUBMISSION_COLUMNS} FROM submissions WHERE id = ?1"),
+                        params![id],
+                        Submission::from_row,
+                    )
+                    .optional()?;
+                Ok(submission)
+            })
+            .await?;
+        self.decrypt(submission.iter_mut());
+
+        Ok(submission)
+    }
+
+    fn decrypt<'a>(&self, submissions: impl IntoIterator<Item = &'a mut Submission>) {
+        for submission in submissions {
+            self.cipher.open_payload(
+                &mut submission.payload,
+                format_args!("submission #{}", submission.id),
+            );
+        }
+    }
+}
+
+/// Inserts the submission within the caller's transaction, e.g. along with its outbox job, and
+/// returns its id
+pub(super) fn insert(
+    conn: &rusqlite::Connection,
+    form: &SealedForm,
+    origin: &SubmissionOrigin,
+    status: SubmissionStatus,
+) -> rusqlite::Result<i64> {
+    conn.execute(
+        "INSERT INTO submissions
+             (payload, email_index, status, request_id, client_ip, origin, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?7)",
+        params![
+            form.payload,
+            form.email_index,
+            status.as_str(),
+            origin.request_id,
+            origin.client_ip.to_string(),
+            origin.origin,
+            unix_now()
+        ],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Moves the submission behind the ticket to the given status, within the caller's transaction
+pub(super) fn set_status(
+    conn: &rusqlite::Connection,
+    ticket: i64,
+    status: SubmissionStatus,
+    last_error: Option<&str>,
+) -> rusqlite::Result<()> {
+    let now = unix_now();
+    let sent_at = (status == SubmissionStatus::Sent).then_some(now);
+
+    conn.execute(
+        "UPDATE submissions
+         SET status = ?2, last_error = ?3, sent_at = COALESCE(?4, sent_at), updated_at = ?5
+         WHERE ticket = ?1",
+        params![ticket, status.as_str(), last_error, sent_at, now],
+    )?;
+    Ok(())
+}
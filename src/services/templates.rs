@@ -0,0 +1,179 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use anyhow::Context;
+use minijinja::{Environment, ErrorKind, UndefinedBehavior, context, path_loader};
+use notify::{Event, RecursiveMode, Watcher};
+use serde::Serialize;
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::mpsc,
+    task::JoinHandle,
+};
+
+use crate::shutdown::Shutdown;
+
+pub const LEAD_TEXT: &str = "lead.txt.j2";
+pub const LEAD_HTML: &str = "lead.html.j2";
+pub const AUTO_REPLY_TEXT: &str = "auto-reply.txt.j2";
+pub const AUTO_REPLY_HTML: &str = "auto-reply.html.j2";
+
+/// The subject template comes from the configs rather than from the directory
+const SUBJECT: &str = "subject.txt";
+
+/// Every template the service can't work without, translations in `<locale>/` are optional
+const REQUIRED_TEMPLATES: &[&str] = &[LEAD_TEXT, LEAD_HTML, AUTO_REPLY_TEXT, AUTO_REPLY_HTML];
+
+/// Editors tend to write a file in a few steps, so the events are coalesced for a moment
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Email templates loaded from a directory, swapped atomically on reload
+#[derive(Clone, Debug)]
+pub struct Templates {
+    dir: PathBuf,
+    subject: String,
+    env: Arc<RwLock<Environment<'static>>>,
+}
+
+impl Templates {
+    pub fn load(dir: &str, subject: &str) -> anyhow::Result<Self> {
+        let dir = PathBuf::from(dir);
+        let subject = subject.to_string();
+        let env = build_environment(&dir, &subject)?;
+
+        tracing::info!("templates loaded from {}", dir.display());
+
+        Ok(Self { dir, subject, env: Arc::new(RwLock::new(env)) })
+    }
+
+    /// Renders the subject as a single line, whatever the template produces
+    pub fn render_subject<S: Serialize>(&self, ctx: S) -> Result<String, minijinja::Error> {
+        let env = self.env.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let subject = env.get_template(SUBJECT)?.render(ctx)?;
+
+        Ok(subject
+            .split(|char: char| char.is_whitespace() || char.is_control())
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>()
+            .join(" "))
+    }
+
+    /// Renders the most specific translation of the template, the root one is the last resort
+    pub fn render<S: Serialize>(
+        &self,
+        name: &str,
+        locales: &[String],
+        ctx: S,
+    ) -> Result<String, minijinja::Error> {
+        let env = self.env.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        for locale in locales {
+            match env.get_template(&format!("{locale}/{name}")) {
+                Ok(template) => return template.render(ctx),
+                Err(err) if err.kind() == ErrorKind::TemplateNotFound => continue,
+                Err(err) => return Err(err),
+            }
+        }
+
+        env.get_template(name)?.render(ctx)
+    }
+
+    /// Rebuilds the templates, keeping the current ones if the new ones are broken
+    pub fn reload(&self) -> anyhow::Result<()> {
+        let env = build_environment(&self.dir, &self.subject)?;
+        *self.env.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = env;
+
+        Ok(())
+    }
+
+    /// Reloads the templates whenever the directory changes or the process gets a SIGHUP
+    pub fn spawn_watcher(&self, shutdown: &Shutdown) -> anyhow::Result<JoinHandle<()>> {
+        let (tx, mut rx) = mpsc::channel::<()>(1);
+
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            if event.is_ok_and(|event| !event.kind.is_access()) {
+                let _ = tx.try_send(());
+            }
+        })
+        .context("couldn't create the templates watcher")?;
+        watcher
+            .watch(&self.dir, RecursiveMode::Recursive)
+            .context("couldn't watch the templates directory")?;
+
+        let mut hangup = signal(SignalKind::hangup()).context("couldn't listen for SIGHUP")?;
+        let templates = self.clone();
+        let stop = shutdown.clone();
+
+        Ok(shutdown.spawn(async move {
+            // The watcher stops as soon as it's dropped
+            let _watcher = watcher;
+
+            loop {
+                let reason = tokio::select! {
+                    Some(()) = rx.recv() => {
+                        tokio::time::sleep(RELOAD_DEBOUNCE).await;
+                        while rx.try_recv().is_ok() {}
+                        "file change"
+                    }
+                    Some(()) = hangup.recv() => "SIGHUP",
+                    _ = stop.cancelled() => break,
+                    else => break,
+                };
+
+                match templates.reload() {
+                    Ok(()) => tracing::info!("templates reloaded on {reason}"),
+                    Err(err) => {
+                        tracing::error!("templates reload on {reason} failed: {:?}", err);
+                    }
+                }
+            }
+        }))
+    }
+}
+
+fn build_environment(dir: &Path, subject: &str) -> anyhow::Result<Environment<'static>> {
+    let mut env = Environment::new();
+    env.set_loader(path_loader(dir));
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    env.add_template_owned(SUBJECT, subject.to_string()).context("invalid subject template")?;
+
+    // Rendering against a sample catches both syntax errors and unknown variables
+    let sample = context! {
+        name => "Jane Doe",
+        email => "jane@example.com",
+        project_description => "A sample project description",
+        excerpt => "A sample project…",
+        min_budget => 1_000,
+        max_budget => 5_000,
+        ticket => 1,
+    };
+    let mut names =
+        [SUBJECT].iter().chain(REQUIRED_TEMPLATES).map(|name| name.to_string()).collect::<Vec<_>>();
+    for entry in std::fs::read_dir(dir).context("couldn't read the templates directory")? {
+        let path = entry.context("couldn't read the templates directory")?.path();
+        let Some(locale) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if !path.is_dir() {
+            continue;
+        }
+
+        names.extend(
+            REQUIRED_TEMPLATES
+                .iter()
+                .filter(|name| path.join(name).is_file())
+                .map(|name| format!("{locale}/{name}")),
+        );
+    }
+
+    for name in names {
+        env.get_template(&name)
+            .and_then(|template| template.render(&sample))
+            .with_context(|| format!("invalid template <{name}> in {}", dir.display()))?;
+    }
+
+    Ok(env)
+}
@@ -0,0 +1,141 @@
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use lettre::{AsyncFileTransport, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::Deserialize;
+use tokio::io::{AsyncWriteExt, stdout};
+
+use crate::{api::errors::EmailErrors, configs::AppConfigs};
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MailTransportKind {
+    #[default]
+    Smtp,
+    File,
+    Stdout,
+    Memory,
+}
+
+#[async_trait]
+pub trait MailTransport: Debug + Send + Sync {
+    async fn send(&self, message: &Message) -> Result<(), EmailErrors>;
+}
+
+/// Delivers messages through the configured SMTPS relay
+#[derive(Clone, Debug)]
+pub struct SmtpMailTransport(AsyncSmtpTransport<Tokio1Executor>);
+
+impl SmtpMailTransport {
+    pub fn new(auth: &str, addr: &str, connection_timeout: u64) -> anyhow::Result<Self> {
+        let timeout = Some(Duration::from_millis(connection_timeout));
+        let url = format!("smtps://{auth}@{addr}");
+
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::from_url(url.as_str())
+            .inspect_err(|err| tracing::error!("smtp error: {:?}", err))
+            .context("couldn't create SMTP transport from URL")?
+            .timeout(timeout)
+            .build();
+
+        Ok(Self(transport))
+    }
+}
+
+#[async_trait]
+impl MailTransport for SmtpMailTransport {
+    async fn send(&self, message: &Message) -> Result<(), EmailErrors> {
+        self.0.send(message.clone()).await?;
+        Ok(())
+    }
+}
+
+/// Drops every message as an `.eml` file into a directory
+#[derive(Clone, Debug)]
+pub struct FileMailTransport(AsyncFileTransport<Tokio1Executor>);
+
+impl FileMailTransport {
+    pub fn new(dir: &str) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(dir)
+            .inspect_err(|err| tracing::error!("mail drop dir error: {:?}", err))
+            .context("couldn't create the mail drop directory")?;
+
+        Ok(Self(AsyncFileTransport::<Tokio1Executor>::new(dir)))
+    }
+}
+
+#[async_trait]
+impl MailTransport for FileMailTransport {
+    async fn send(&self, message: &Message) -> Result<(), EmailErrors> {
+        let id = self.0.send(message.clone()).await?;
+        tracing::debug!("message dropped as {id}.eml");
+        Ok(())
+    }
+}
+
+/// Prints every message in its raw RFC 5322 form to the stdout
+#[derive(Clone, Debug, Default)]
+pub struct StdoutMailTransport;
+
+#[async_trait]
+impl MailTransport for StdoutMailTransport {
+    async fn send(&self, message: &Message) -> Result<(), EmailErrors> {
+        let mut out = stdout();
+        out.write_all(&message.formatted()).await?;
+        out.write_all(b"\n").await?;
+        out.flush().await?;
+        Ok(())
+    }
+}
+
+/// Keeps every message in memory, so they can be inspected afterwards
+#[derive(Clone, Debug, Default)]
+pub struct MemoryMailTransport {
+    messages: Arc<Mutex<Vec<Message>>>,
+}
+
+impl MemoryMailTransport {
+    #[cfg(test)]
+    pub fn messages(&self) -> Vec<Message> {
+        self.messages.lock().map(|messages| messages.clone()).unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl MailTransport for MemoryMailTransport {
+    async fn send(&self, message: &Message) -> Result<(), EmailErrors> {
+        if let Ok(mut messages) = self.messages.lock() {
+            messages.push(message.clone());
+            tracing::debug!("message kept in memory ({} in total)", messages.len());
+        }
+        Ok(())
+    }
+}
+
+pub fn build_transport(configs: &AppConfigs) -> anyhow::Result<Arc<dyn MailTransport>> {
+    let transport: Arc<dyn MailTransport> = match configs.mail_transport {
+        MailTransportKind::Smtp => {
+            let auth = configs.smtp_auth.as_deref().context("<smtp_auth> is missing")?;
+            let addr = configs.smtp_addr.as_deref().context("<smtp_addr> is missing")?;
+            Arc::new(SmtpMailTransport::new(
+                auth,
+                addr,
+                configs.smtp_connection_timeout,
+            )?)
+        }
+        MailTransportKind::File => {
+            let dir = configs.mail_drop_dir.as_deref().context("<mail_drop_dir> is missing")?;
+            Arc::new(FileMailTransport::new(dir)?)
+        }
+        MailTransportKind::Stdout => Arc::new(StdoutMailTransport),
+        MailTransportKind::Memory => Arc::new(MemoryMailTransport::default()),
+    };
+
+    tracing::info!("mail transport: {:?}", configs.mail_transport);
+
+    Ok(transport)
+}
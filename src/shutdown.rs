@@ -0,0 +1,126 @@
+use std::{future::Future, net::SocketAddr, time::Duration};
+
+use anyhow::Context;
+use axum::Router;
+use tokio::{
+    net::TcpListener,
+    signal::unix::{SignalKind, signal},
+    task::JoinHandle,
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+use crate::SENTRY_GUARD;
+
+const SENTRY_FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Stops the server and the background workers, giving them a deadline to finish
+#[derive(Clone, Debug, Default)]
+pub struct Shutdown {
+    token: CancellationToken,
+    tracker: TaskTracker,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spawns a background worker the shutdown waits for
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.tracker.spawn(future)
+    }
+
+    /// Resolves as soon as the shutdown begins
+    pub async fn cancelled(&self) {
+        self.token.cancelled().await
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Serves the app until SIGTERM or SIGINT, then drains it
+    pub async fn serve(
+        &self,
+        listener: TcpListener,
+        app: Router,
+        timeout: Duration,
+    ) -> anyhow::Result<()> {
+        let mut terminate =
+            signal(SignalKind::terminate()).context("couldn't listen for SIGTERM")?;
+        let mut interrupt =
+            signal(SignalKind::interrupt()).context("couldn't listen for SIGINT")?;
+
+        let token = self.token.clone();
+        let app = app.into_make_service_with_connect_info::<SocketAddr>();
+        let server = axum::serve(listener, app)
+            .with_graceful_shutdown(async move { token.cancelled().await })
+            .into_future();
+        let mut server = self.tracker.spawn(server);
+
+        tokio::select! {
+            result = &mut server => {
+                return result.context("the server panicked")?.context("couldn't serve the app");
+            }
+            _ = terminate.recv() => tracing::info!("shutting down on SIGTERM"),
+            _ = interrupt.recv() => tracing::info!("shutting down on SIGINT"),
+            _ = self.token.cancelled() => {}
+        }
+
+        self.drain(timeout).await;
+
+        Ok(())
+    }
+
+    /// Stops accepting requests, waits for the in-flight ones and the workers, then flushes Sentry
+    pub async fn drain(&self, timeout: Duration) {
+        self.token.cancel();
+        self.tracker.close();
+
+        match tokio::time::timeout(timeout, self.tracker.wait()).await {
+            Ok(()) => tracing::info!("shutdown drained"),
+            Err(_) => tracing::warn!(
+                "shutdown deadline of {} sec exceeded, {} task(s) abandoned",
+                timeout.as_secs(),
+                self.tracker.len()
+            ),
+        }
+
+        if let Some(guard) = SENTRY_GUARD.get() {
+            guard.flush(Some(SENTRY_FLUSH_TIMEOUT));
+        }
+    }
+}
+
+/// Serves the app on Shuttle
+#[cfg(feature = "shuttle")]
+pub struct ShuttleService {
+    pub app: Router,
+    pub shutdown: Shutdown,
+    pub timeout: Duration,
+}
+
+#[cfg(feature = "shuttle")]
+#[shuttle_runtime::async_trait]
+impl shuttle_runtime::Service for ShuttleService {
+    async fn bind(self, addr: SocketAddr) -> Result<(), shuttle_runtime::Error> {
+        use tokio::runtime::{Handle, RuntimeFlavor};
+
+        let listener = TcpListener::bind(addr).await.context("couldn't bind the listener")?;
+        let serve = self.shutdown.serve(listener, self.app, self.timeout);
+
+        // The runtime exits as soon as it sees SIGTERM on its own, dropping this future, so the
+        // serving holds the poll until the drain is over, which only a multi-thread runtime allows
+        let handle = Handle::current();
+        match handle.runtime_flavor() {
+            RuntimeFlavor::MultiThread => tokio::task::block_in_place(|| handle.block_on(serve))?,
+            _ => serve.await?,
+        }
+
+        Ok(())
+    }
+}